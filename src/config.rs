@@ -0,0 +1,392 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::gcode;
+
+/// Settings of the application, stored as `config.toml` in the config directory of the user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Name of the profile chosen in the connection bar
+    pub active_profile: String,
+    pub serial: SerialConfig,
+    pub polling: PollingConfig,
+    pub jog: JogConfig,
+    pub ui: UiConfig,
+    /// There is always at least one profile
+    pub profiles: Vec<Profile>,
+    /// The file could not be read nor moved away, so it is not overwritten
+    #[serde(skip)]
+    keep_file: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let profile = Profile::default();
+        Config {
+            active_profile: profile.name.clone(),
+            serial: SerialConfig::default(),
+            polling: PollingConfig::default(),
+            jog: JogConfig::default(),
+            ui: UiConfig::default(),
+            profiles: vec![profile],
+            keep_file: false,
+        }
+    }
+}
+
+/// The firmware a printer runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flavour {
+    /// Taken from the answer to `M115`
+    Auto,
+    Marlin,
+    RepRapFirmware,
+    Klipper,
+    Smoothieware,
+    Prusa,
+}
+
+impl Flavour {
+    pub const ALL: [Flavour; 6] = [
+        Flavour::Auto,
+        Flavour::Marlin,
+        Flavour::RepRapFirmware,
+        Flavour::Klipper,
+        Flavour::Smoothieware,
+        Flavour::Prusa,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Flavour::Auto => "Detect from M115",
+            Flavour::Marlin => "Marlin",
+            Flavour::RepRapFirmware => "RepRapFirmware",
+            Flavour::Klipper => "Klipper",
+            Flavour::Smoothieware => "Smoothieware",
+            Flavour::Prusa => "Prusa firmware",
+        }
+    }
+}
+
+/// Everything that differs from printer to printer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    /// The port used last time
+    pub port: Option<String>,
+    /// USB serial number of the printer, preferred over the port name
+    pub serial_number: Option<String>,
+    /// Zero detects the baud rate
+    pub baud_rate: u32,
+    /// Size of the build volume in mm
+    pub build_volume: [f32; 3],
+    /// Lowest position of X, Y and Z in mm. Negative if the nozzle can move past the bed,
+    /// like the purge line of a Prusa in front of it.
+    pub axis_min: [f32; 3],
+    pub extruders: u32,
+    pub heated_bed: bool,
+    pub heated_chamber: bool,
+    pub flavour: Flavour,
+    /// Sent before the first line of a job
+    pub start_script: String,
+    /// Sent after the last line of a job
+    pub end_script: String,
+    /// Sent after a job was cancelled, before the heaters are turned off
+    pub cancel_script: String,
+    /// Highest temperatures that can be set in °C
+    pub max_hotend_temperature: f32,
+    pub max_bed_temperature: f32,
+    pub max_chamber_temperature: f32,
+    /// Filament diameter in mm and density in g/cm³, needed for the weight of a job
+    pub filament_diameter: f32,
+    pub filament_density: f32,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: "Default".to_string(),
+            port: None,
+            serial_number: None,
+            baud_rate: 250_000,
+            build_volume: [220.0, 220.0, 250.0],
+            axis_min: [0.0; 3],
+            extruders: 1,
+            heated_bed: true,
+            heated_chamber: false,
+            flavour: Flavour::Auto,
+            start_script: String::new(),
+            end_script: String::new(),
+            cancel_script: "G91\nG1 Z10 F600\nG90\nM84".to_string(),
+            max_hotend_temperature: 260.0,
+            max_bed_temperature: 110.0,
+            max_chamber_temperature: 60.0,
+            // PLA
+            filament_diameter: 1.75,
+            filament_density: 1.24,
+        }
+    }
+}
+
+impl Profile {
+    /// An error if the line sets a heater above its maximum
+    pub fn check_temperature(&self, line: &gcode::Line) -> Result<(), String> {
+        let (heater, max) = if line.is('M', 104) || line.is('M', 109) {
+            ("hotend", self.max_hotend_temperature)
+        } else if line.is('M', 140) || line.is('M', 190) {
+            ("bed", self.max_bed_temperature)
+        } else if line.is('M', 141) || line.is('M', 191) {
+            ("chamber", self.max_chamber_temperature)
+        } else {
+            return Ok(());
+        };
+        // M109 and M190 take the target as S or R
+        match line.get('S').or_else(|| line.get('R')) {
+            Some(target) if target > f64::from(max) => Err(format!(
+                "{} °C is above the maximum {} temperature of {} °C",
+                target, heater, max
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Serial parameters besides the baud rate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialConfig {
+    pub data_bits: u8,
+    /// `none`, `odd` or `even`
+    pub parity: String,
+    pub stop_bits: u8,
+    /// `none`, `software` or `hardware`
+    pub flow_control: String,
+    /// Read timeout in milliseconds
+    pub timeout: u64,
+    /// How long the firmware may take to answer after connecting, in milliseconds
+    pub handshake_timeout: u64,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            data_bits: 8,
+            parity: "none".to_string(),
+            stop_bits: 1,
+            flow_control: "none".to_string(),
+            timeout: 10,
+            handshake_timeout: 15000,
+        }
+    }
+}
+
+/// How often the state of the printer is queried, in milliseconds. Zero turns the query off.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollingConfig {
+    pub temperature: u64,
+    pub position: u64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        PollingConfig {
+            temperature: 1000,
+            position: 1000,
+        }
+    }
+}
+
+/// Step sizes in mm and feedrates in mm/min of the jog buttons
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JogConfig {
+    pub xy_step: f32,
+    pub z_step: f32,
+    pub e_step: f32,
+    pub xy_feedrate: f32,
+    pub z_feedrate: f32,
+    pub e_feedrate: f32,
+}
+
+impl Default for JogConfig {
+    fn default() -> Self {
+        JogConfig {
+            xy_step: 10.0,
+            z_step: 1.0,
+            e_step: 5.0,
+            xy_feedrate: 3000.0,
+            z_feedrate: 600.0,
+            e_feedrate: 300.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    pub maximized: bool,
+    pub dark_theme: bool,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        UiConfig {
+            maximized: true,
+            dark_theme: false,
+        }
+    }
+}
+
+impl Config {
+    /// The active profile, the first one if there is none with its name
+    pub fn profile(&self) -> &Profile {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == self.active_profile)
+            .unwrap_or(&self.profiles[0])
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        let index = self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == self.active_profile)
+            .unwrap_or(0);
+        &mut self.profiles[index]
+    }
+
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect()
+    }
+
+    /// Read the config file, the defaults are used if there is none.
+    /// A file that cannot be read is moved to `config.toml.bak` before anything is saved,
+    /// the defaults are returned together with the error then.
+    pub fn load() -> (Config, Option<String>) {
+        let path = match path() {
+            Some(path) => path,
+            None => return (Config::default(), None),
+        };
+        let error = match std::fs::read_to_string(&path) {
+            Ok(text) => match Config::parse(&text) {
+                Ok(config) => return (config, None),
+                Err(err) => err.to_string(),
+            },
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return (Config::default(), None)
+            }
+            Err(err) => err.to_string(),
+        };
+
+        let mut config = Config::default();
+        let backup = path.with_extension("toml.bak");
+        let message = match std::fs::rename(&path, &backup) {
+            Ok(()) => format!(
+                "Cannot read the settings in {}: {}. The file was moved to {}, \
+                 the defaults are used",
+                path.display(),
+                error,
+                backup.display()
+            ),
+            Err(_) => {
+                config.keep_file = true;
+                format!(
+                    "Cannot read the settings in {}: {}. The defaults are used and not saved",
+                    path.display(),
+                    error
+                )
+            }
+        };
+        (config, Some(message))
+    }
+
+    /// Write the config file, creating the directory if needed
+    pub fn save(&self) -> Result<(), String> {
+        let path = path().ok_or_else(|| "No config directory".to_string())?;
+        if self.keep_file {
+            return Err(format!(
+                "{} could not be read, fix or remove it first",
+                path.display()
+            ));
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+        let text = toml::to_string(self).map_err(|err| err.to_string())?;
+        std::fs::write(&path, text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Missing values are taken from the defaults
+    pub fn parse(text: &str) -> Result<Config, toml::de::Error> {
+        let mut config: Config = toml::from_str(text)?;
+        if config.profiles.is_empty() {
+            config.profiles.push(Profile::default());
+        }
+        Ok(config)
+    }
+}
+
+/// `config.toml` in the XDG config directory, e.g. `~/.config/gcode1000/config.toml`
+fn path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("gcode1000").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut config = Config::default();
+        config.profile_mut().port = Some("/dev/ttyUSB0".to_string());
+        config.profile_mut().baud_rate = 0;
+        config.profiles.push(Profile {
+            name: "Voron".to_string(),
+            flavour: Flavour::Klipper,
+            build_volume: [350.0, 350.0, 340.0],
+            ..Profile::default()
+        });
+        config.active_profile = "Voron".to_string();
+        config.serial.parity = "even".to_string();
+        config.jog.xy_step = 0.1;
+        config.ui.dark_theme = true;
+
+        let text = toml::to_string(&config).unwrap();
+        let parsed = Config::parse(&text).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.profile().flavour, Flavour::Klipper);
+    }
+
+    #[test]
+    fn partial_file() {
+        let config = Config::parse("[jog]\nz_step = 0.1\n\n[ui]\ndark_theme = true\n").unwrap();
+        assert_eq!(config.jog.z_step, 0.1);
+        assert_eq!(config.jog.xy_step, JogConfig::default().xy_step);
+        assert!(config.ui.dark_theme && config.ui.maximized);
+        assert_eq!(config.serial, SerialConfig::default());
+        assert_eq!(config.profile(), &Profile::default());
+
+        // An unknown profile falls back to the first one
+        let config = Config::parse("active_profile = \"Gone\"\nprofiles = []\n").unwrap();
+        assert_eq!(config.profile().name, "Default");
+
+        assert!(Config::parse("[polling]\ntemperature = \"often\"").is_err());
+    }
+    #[test]
+    fn temperature_limits() {
+        let profile = Profile::default();
+        let check = |line: &str| profile.check_temperature(&gcode::Line::parse(line).unwrap());
+        assert!(check("M104 S210").is_ok());
+        assert!(check("M109 R280").is_err());
+        assert!(check("M140 S120").is_err());
+        assert!(check("M190").is_ok());
+        assert!(check("G1 X300 S500").is_ok());
+    }
+}
@@ -2,7 +2,13 @@ use gtk::prelude::*;
 use relm::{connect, Relm};
 use relm_derive::Msg;
 use serialport::prelude::*;
-use std::io::Read;
+
+use crate::config;
+use crate::network;
+use crate::queue;
+use crate::response;
+use crate::transport::{self, SerialTransport, Transport};
+use crate::virtual_printer;
 
 #[derive(Msg)]
 pub enum Msg {
@@ -11,19 +17,120 @@ pub enum Msg {
     SendLine(String),
     Disconnect,
     ReciveLine(String),
+    SetLineNumbers(bool),
+    StreamingModeChanged,
+    SetStreamingMode(queue::StreamingMode),
+    SetSerialSettings(SerialSettings),
+    /// Result of the automatic baud rate detection
+    BaudRateDetected(Option<u32>),
+    /// Port, baud rate and USB serial number of a new connection, zero for the automatic detection
+    Connecting(String, u32, Option<String>),
+    ProfileChanged,
+    SelectProfile(String),
+    /// Names of all profiles and the active one
+    SetProfiles(Vec<String>, String),
+    SetProfile(config::Profile),
+    /// Scan for ports, e.g. after a printer was plugged in
+    RefreshPorts,
+    /// The port cannot be opened, the firmware does not answer or the connection was lost
+    ConnectionFailed(String),
+}
+
+/// Serial parameters besides the baud rate, set in the Settings tab
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerialSettings {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    /// How long a read waits for data, commands are only sent in between
+    pub timeout: std::time::Duration,
+    /// How long the firmware may take to answer after the port was opened
+    pub handshake_timeout: std::time::Duration,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        SerialSettings {
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: std::time::Duration::from_millis(10),
+            handshake_timeout: std::time::Duration::from_secs(15),
+        }
+    }
 }
 
+impl From<&config::SerialConfig> for SerialSettings {
+    fn from(config: &config::SerialConfig) -> Self {
+        SerialSettings {
+            data_bits: match config.data_bits {
+                5 => DataBits::Five,
+                6 => DataBits::Six,
+                7 => DataBits::Seven,
+                _ => DataBits::Eight,
+            },
+            parity: match config.parity.as_str() {
+                "odd" => Parity::Odd,
+                "even" => Parity::Even,
+                _ => Parity::None,
+            },
+            stop_bits: match config.stop_bits {
+                2 => StopBits::Two,
+                _ => StopBits::One,
+            },
+            flow_control: match config.flow_control.as_str() {
+                "software" => FlowControl::Software,
+                "hardware" => FlowControl::Hardware,
+                _ => FlowControl::None,
+            },
+            timeout: std::time::Duration::from_millis(config.timeout),
+            handshake_timeout: std::time::Duration::from_millis(config.handshake_timeout),
+        }
+    }
+}
+
+/// Baud rates offered in the connection bar, any other rate can be typed in
+const BAUD_RATES: [u32; 9] = [
+    250_000, 115_200, 57600, 38400, 19200, 9600, 230_400, 500_000, 1_000_000,
+];
+/// Baud rates tried one after the other by the automatic detection
+const AUTO_BAUD_RATES: [u32; 6] = [250_000, 115_200, 57600, 230_400, 500_000, 1_000_000];
+/// Interval in milliseconds between two scans for new ports
+const PORT_SCAN_INTERVAL: u32 = 2000;
+/// How long to wait for an answer at every baud rate, boards may reset when the port is opened
+const PROBE_TIME: std::time::Duration = std::time::Duration::from_secs(4);
+/// Wait before the first `M110 N0` of the handshake
+const HANDSHAKE_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+/// Interval between two `M110 N0` of the handshake
+const HANDSHAKE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// How long to wait for the answers to earlier probes once the firmware answered one
+const HANDSHAKE_QUIET: std::time::Duration = std::time::Duration::from_millis(500);
+
 pub struct Model {
     connection_thread: Option<std::thread::JoinHandle<()>>,
     stream: relm::EventStream<Msg>,
     thread_command: Option<std::sync::mpsc::Sender<ThreadCmd>>,
     connection_active: bool,
+    serial_settings: SerialSettings,
+    /// The active printer profile
+    profile: config::Profile,
+    profile_names: Vec<String>,
+    /// The ports in the port combobox
+    ports: Vec<serialport::SerialPortInfo>,
 }
 
 pub struct Widgets {
+    profile_combobox: gtk::ComboBoxText,
     port_combobox: gtk::ComboBoxText,
+    /// Shown if the ports cannot be listed
+    port_warning: gtk::Image,
+    baud_combobox: gtk::ComboBoxText,
     connect_btn: gtk::Button,
     disconnect_btn: gtk::Button,
+    mode_combobox: gtk::ComboBoxText,
+    rx_buffer_spin: gtk::SpinButton,
     root: gtk::Box,
 }
 
@@ -32,10 +139,20 @@ enum ThreadCmd {
     SendLine(String),
 }
 
+/// Why the connection thread ends before the connection is active
+enum Stop {
+    /// Disconnect was clicked
+    Disconnect,
+    Failed(String),
+}
+
 enum ThreadStatus {
     ConnectionError,
+    /// Why the connection could not be made or was lost
+    ConnectionFailed(String),
     RecivedLine(String),
     ConnectionActive,
+    BaudRateDetected(Option<u32>),
 }
 
 pub struct Widget {
@@ -45,15 +162,23 @@ pub struct Widget {
 
 impl relm::Update for Widget {
     type Model = Model;
-    type ModelParam = ();
+    type ModelParam = config::Config;
     type Msg = Msg;
 
-    fn model(relm: &Relm<Self>, _param: Self::ModelParam) -> Self::Model {
+    fn subscriptions(&mut self, relm: &Relm<Self>) {
+        relm::interval(relm.stream(), PORT_SCAN_INTERVAL, || Msg::RefreshPorts);
+    }
+
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
         Model {
             connection_thread: None,
             stream: relm.stream().clone(),
             thread_command: None,
             connection_active: false,
+            serial_settings: SerialSettings::from(&config.serial),
+            profile: config.profile().clone(),
+            profile_names: config.profile_names(),
+            ports: Vec::new(),
         }
     }
 
@@ -69,6 +194,96 @@ impl relm::Update for Widget {
                 }
             }
             Msg::ReciveLine(_line) => (),
+            Msg::SetLineNumbers(_enabled) => (),
+            Msg::StreamingModeChanged => {
+                let buffered = self
+                    .widgets
+                    .mode_combobox
+                    .get_active_id()
+                    .map_or(false, |id| id == "buffered");
+                self.widgets.rx_buffer_spin.set_sensitive(buffered);
+                let mode = if buffered {
+                    queue::StreamingMode::Buffered {
+                        rx_buffer_size: self.widgets.rx_buffer_spin.get_value_as_int() as usize,
+                    }
+                } else {
+                    queue::StreamingMode::PingPong
+                };
+                self.model.stream.emit(Msg::SetStreamingMode(mode));
+            }
+            Msg::SetStreamingMode(_mode) => (),
+            Msg::SetSerialSettings(settings) => self.model.serial_settings = settings,
+            Msg::Connecting(_port, _baud_rate, _serial_number) => (),
+            Msg::ConnectionFailed(_error) => (),
+            Msg::ProfileChanged => {
+                if let Some(name) = self.widgets.profile_combobox.get_active_id() {
+                    self.model.stream.emit(Msg::SelectProfile(name.to_string()));
+                }
+            }
+            Msg::SelectProfile(_name) => (),
+            Msg::SetProfiles(names, active) => {
+                let combobox = &self.widgets.profile_combobox;
+                combobox.remove_all();
+                for name in names.iter() {
+                    combobox.append(Some(name), name);
+                }
+                combobox.set_active_id(Some(&active));
+            }
+            Msg::SetProfile(profile) => {
+                // Keep the port and baud rate chosen by hand as long as the profile stays the same
+                let changed = profile.name != self.model.profile.name
+                    || profile.port != self.model.profile.port
+                    || profile.serial_number != self.model.profile.serial_number
+                    || profile.baud_rate != self.model.profile.baud_rate;
+                if changed {
+                    select_port(&self.widgets.port_combobox, &self.model.ports, &profile);
+                    select_baud_rate(&self.widgets.baud_combobox, profile.baud_rate);
+                }
+                self.model.profile = profile;
+            }
+            Msg::RefreshPorts => {
+                let ports = match get_ports() {
+                    Ok(ports) => {
+                        set_port_warning(&self.widgets.port_warning, None);
+                        ports
+                    }
+                    Err(err) => {
+                        set_port_warning(&self.widgets.port_warning, Some(&err));
+                        Vec::new()
+                    }
+                };
+                if ports != self.model.ports {
+                    let port_combobox = &self.widgets.port_combobox;
+                    let target = get_target(port_combobox);
+                    fill_ports(port_combobox, &ports);
+                    let selected = ports.iter().position(|port| port.port_name == target);
+                    match selected {
+                        // The printer of the profile wins, whatever port it was plugged in to
+                        Some(index)
+                            if find_serial_number(&ports, &self.model.profile).is_none() =>
+                        {
+                            port_combobox.set_active(Some(index as u32))
+                        }
+                        // A network bridge that was typed in stays
+                        None if network::is_network(&target) => {
+                            set_entry_text(port_combobox, &target)
+                        }
+                        None if target == virtual_printer::TARGET => {
+                            port_combobox.set_active_id(Some(&target));
+                        }
+                        _ => select_port(port_combobox, &ports, &self.model.profile),
+                    }
+                    self.model.ports = ports;
+                }
+            }
+            Msg::BaudRateDetected(baud_rate) => {
+                // Connect with the found baud rate right away next time
+                if let Some(index) = baud_rate
+                    .and_then(|baud_rate| BAUD_RATES.iter().position(|rate| *rate == baud_rate))
+                {
+                    self.widgets.baud_combobox.set_active(Some(index as u32));
+                }
+            }
             Msg::Disconnect => {
                 self.model.connection_active = false;
                 // Send Stop signal to thread
@@ -87,31 +302,58 @@ impl relm::Update for Widget {
                     self.update(Msg::Disconnect);
                 } else {
                     // Open a connection to the port specified in the connection tab
-                    let port_settings = serialport::SerialPortSettings {
-                        baud_rate: 250_000,
-                        stop_bits: StopBits::One,
-                        data_bits: DataBits::Eight,
-                        flow_control: FlowControl::None,
-                        parity: Parity::None,
-                        timeout: std::time::Duration::from_millis(10),
-                    };
-                    let connection_string = self
+                    let baud_rate_text = self
                         .widgets
-                        .port_combobox
+                        .baud_combobox
                         .get_active_text()
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "".to_string());
+                        .map(|text| text.trim().to_string())
+                        .unwrap_or_default();
+                    // Zero stands for detecting the baud rate
+                    let baud_rate = match baud_rate_text.parse::<u32>() {
+                        Ok(baud_rate) if baud_rate > 0 => baud_rate,
+                        _ if baud_rate_text == "Auto" => 0,
+                        _ => {
+                            self.model.stream.emit(Msg::ConnectionFailed(format!(
+                                "Cannot connect, \"{}\" is not a baud rate",
+                                baud_rate_text
+                            )));
+                            return;
+                        }
+                    };
+                    let serial_settings = self.model.serial_settings;
+                    let port_settings = serialport::SerialPortSettings {
+                        baud_rate,
+                        stop_bits: serial_settings.stop_bits,
+                        data_bits: serial_settings.data_bits,
+                        flow_control: serial_settings.flow_control,
+                        parity: serial_settings.parity,
+                        timeout: serial_settings.timeout,
+                    };
+                    let connection_string = get_target(&self.widgets.port_combobox);
+                    // Remember the printer by its serial number
+                    let serial_number = self
+                        .model
+                        .ports
+                        .iter()
+                        .find(|port| port.port_name == connection_string)
+                        .and_then(usb_info)
+                        .and_then(|info| info.serial_number.clone());
 
-                    if let Ok((mpsc_tx, thread_handle)) = create_connection_thread(
+                    self.model.stream.emit(Msg::Connecting(
+                        connection_string.clone(),
+                        baud_rate,
+                        serial_number,
+                    ));
+                    let (mpsc_tx, thread_handle) = create_connection_thread(
                         connection_string,
                         port_settings,
+                        serial_settings.handshake_timeout,
                         self.model.stream.clone(),
-                    ) {
-                        self.widgets.connect_btn.set_sensitive(false);
-                        self.widgets.disconnect_btn.set_sensitive(true);
-                        self.model.thread_command = Some(mpsc_tx);
-                        self.model.connection_thread = Some(thread_handle);
-                    }
+                    );
+                    self.widgets.connect_btn.set_sensitive(false);
+                    self.widgets.disconnect_btn.set_sensitive(true);
+                    self.model.thread_command = Some(mpsc_tx);
+                    self.model.connection_thread = Some(thread_handle);
                 }
             }
             Msg::ConnectionActive => self.model.connection_active = true,
@@ -126,19 +368,51 @@ impl relm::Widget for Widget {
         self.widgets.root.clone()
     }
 
-    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+    fn view(relm: &Relm<Self>, mut model: Self::Model) -> Self {
         // Create the status line
         let statusline = gtk::Box::new(gtk::Orientation::Horizontal, 2);
 
-        // Add a simple combobox to choose the port
-        let port_combobox = gtk::ComboBoxText::new();
-        for port in get_ports().iter() {
-            port_combobox.append_text(port);
+        // The printer profiles
+        let profile_combobox = gtk::ComboBoxText::new();
+        for name in model.profile_names.iter() {
+            profile_combobox.append(Some(name), name);
         }
-        port_combobox.set_active(Some(0));
+        profile_combobox.set_active_id(Some(&model.profile.name));
+
+        statusline.pack_start(&gtk::Label::new(Some("Printer:")), false, false, 0);
+        statusline.pack_start(&profile_combobox, false, false, 0);
+
+        // Add a combobox to choose the port, the list is kept up to date.
+        // Network bridges can be typed in as tcp://host:port or rfc2217://host:port.
+        let port_combobox = gtk::ComboBoxText::with_entry();
+        port_combobox.set_tooltip_text(Some("Serial port, tcp://host:port or rfc2217://host:port"));
+        let port_warning =
+            gtk::Image::from_icon_name(Some("dialog-warning-symbolic"), gtk::IconSize::Button);
+        port_warning.set_no_show_all(true);
+        match get_ports() {
+            Ok(ports) => model.ports = ports,
+            Err(err) => set_port_warning(&port_warning, Some(&err)),
+        }
+        fill_ports(&port_combobox, &model.ports);
+        select_port(&port_combobox, &model.ports, &model.profile);
+        let refresh_btn =
+            gtk::Button::from_icon_name(Some("view-refresh-symbolic"), gtk::IconSize::Button);
+        refresh_btn.set_tooltip_text(Some("Scan for ports"));
 
         statusline.pack_start(&gtk::Label::new(Some("Port:")), false, false, 0);
         statusline.pack_start(&port_combobox, false, false, 0);
+        statusline.pack_start(&refresh_btn, false, false, 0);
+        statusline.pack_start(&port_warning, false, false, 0);
+
+        // Common baud rates, others can be typed in
+        let baud_combobox = gtk::ComboBoxText::with_entry();
+        for baud_rate in BAUD_RATES.iter() {
+            baud_combobox.append_text(&baud_rate.to_string());
+        }
+        baud_combobox.append_text("Auto");
+        select_baud_rate(&baud_combobox, model.profile.baud_rate);
+        statusline.pack_start(&gtk::Label::new(Some("Baud:")), false, false, 0);
+        statusline.pack_start(&baud_combobox, false, false, 0);
 
         let connect_btn = gtk::Button::with_label(&"Connect");
         connect_btn
@@ -152,119 +426,495 @@ impl relm::Widget for Widget {
         statusline.pack_start(&disconnect_btn, false, false, 0);
         disconnect_btn.set_sensitive(false);
 
+        // Send line numbers and checksums with every command
+        let checksum_btn = gtk::CheckButton::with_label("Checksums");
+        statusline.pack_start(&checksum_btn, false, false, 0);
+
+        // Safe waits for every ok, buffered keeps the receive buffer of the firmware filled
+        let mode_combobox = gtk::ComboBoxText::new();
+        mode_combobox.append(Some("safe"), "Safe");
+        mode_combobox.append(Some("buffered"), "Buffered");
+        mode_combobox.set_active_id(Some("safe"));
+        let rx_buffer_spin = gtk::SpinButton::with_range(16.0, 4096.0, 1.0);
+        rx_buffer_spin.set_value(128.0);
+        rx_buffer_spin.set_sensitive(false);
+        statusline.pack_start(&gtk::Label::new(Some("Mode:")), false, false, 0);
+        statusline.pack_start(&mode_combobox, false, false, 0);
+        statusline.pack_start(&gtk::Label::new(Some("RX Buffer:")), false, false, 0);
+        statusline.pack_start(&rx_buffer_spin, false, false, 0);
+
+        connect!(
+            relm,
+            profile_combobox,
+            connect_changed(_),
+            Msg::ProfileChanged
+        );
+        connect!(relm, refresh_btn, connect_clicked(_), Msg::RefreshPorts);
         connect!(relm, connect_btn, connect_clicked(_), Msg::Connect);
         connect!(relm, disconnect_btn, connect_clicked(_), Msg::Disconnect);
+        connect!(
+            relm,
+            checksum_btn,
+            connect_toggled(btn),
+            Msg::SetLineNumbers(btn.get_active())
+        );
+        connect!(
+            relm,
+            mode_combobox,
+            connect_changed(_),
+            Msg::StreamingModeChanged
+        );
+        connect!(
+            relm,
+            rx_buffer_spin,
+            connect_value_changed(_),
+            Msg::StreamingModeChanged
+        );
 
         Self {
             widgets: Widgets {
                 root: statusline,
+                profile_combobox,
                 connect_btn,
                 disconnect_btn,
                 port_combobox,
+                port_warning,
+                baud_combobox,
+                mode_combobox,
+                rx_buffer_spin,
             },
             model,
         }
     }
 }
 
-/// Find avaible ports
-fn get_ports() -> Vec<String> {
-    serialport::available_ports()
-        .unwrap()
+/// Select the port of the printer, found by its USB serial number or the port used last time
+fn select_port(
+    port_combobox: &gtk::ComboBoxText,
+    ports: &[serialport::SerialPortInfo],
+    profile: &config::Profile,
+) {
+    let index = find_serial_number(ports, profile).or_else(|| {
+        profile
+            .port
+            .as_ref()
+            .and_then(|name| ports.iter().position(|port| port.port_name == *name))
+    });
+    match (index, &profile.port) {
+        (Some(index), _) => port_combobox.set_active(Some(index as u32)),
+        (None, Some(port)) if network::is_network(port) => set_entry_text(port_combobox, port),
+        (None, Some(port)) if port == virtual_printer::TARGET => {
+            port_combobox.set_active_id(Some(port));
+        }
+        // The virtual printer is only chosen on purpose
+        (None, _) if ports.is_empty() => port_combobox.set_active(None),
+        (None, _) => port_combobox.set_active(Some(0)),
+    }
+}
+
+/// The chosen port or the network bridge typed in
+fn get_target(port_combobox: &gtk::ComboBoxText) -> String {
+    port_combobox
+        .get_active_id()
+        .or_else(|| port_combobox.get_active_text())
+        .map(|target| target.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Index of the port with the USB serial number of the profile
+fn find_serial_number(
+    ports: &[serialport::SerialPortInfo],
+    profile: &config::Profile,
+) -> Option<usize> {
+    let serial_number = profile.serial_number.as_ref()?;
+    ports.iter().position(|port| {
+        usb_info(port).and_then(|info| info.serial_number.as_ref()) == Some(serial_number)
+    })
+}
+
+fn usb_info(port: &serialport::SerialPortInfo) -> Option<&serialport::UsbPortInfo> {
+    match port.port_type {
+        serialport::SerialPortType::UsbPort(ref info) => Some(info),
+        _ => None,
+    }
+}
+
+/// The ports with their USB identity, the port name is the id
+fn fill_ports(port_combobox: &gtk::ComboBoxText, ports: &[serialport::SerialPortInfo]) {
+    port_combobox.remove_all();
+    for port in ports.iter() {
+        port_combobox.append(Some(&port.port_name), &port_label(port));
+    }
+    port_combobox.append(Some(virtual_printer::TARGET), "Virtual printer");
+}
+
+/// E.g. `/dev/ttyACM0 - Prusa Research Original Prusa i3 MK3 (2c99:0002, SN CZPX1234)`
+fn port_label(port: &serialport::SerialPortInfo) -> String {
+    let info = match usb_info(port) {
+        Some(info) => info,
+        None => return port.port_name.clone(),
+    };
+    let name: Vec<&str> = info
+        .manufacturer
         .iter()
-        .map(|port| port.port_name.clone())
-        .collect()
+        .chain(info.product.iter())
+        .map(|text| text.as_str())
+        .collect();
+    let mut identity = format!("{:04x}:{:04x}", info.vid, info.pid);
+    if let Some(ref serial_number) = info.serial_number {
+        identity = format!("{}, SN {}", identity, serial_number);
+    }
+    if name.is_empty() {
+        format!("{} ({})", port.port_name, identity)
+    } else {
+        format!("{} - {} ({})", port.port_name, name.join(" "), identity)
+    }
+}
+
+/// Select a baud rate from the list, zero selects the detection, other rates are typed in
+fn select_baud_rate(baud_combobox: &gtk::ComboBoxText, baud_rate: u32) {
+    match BAUD_RATES.iter().position(|rate| *rate == baud_rate) {
+        Some(index) => baud_combobox.set_active(Some(index as u32)),
+        None if baud_rate == 0 => baud_combobox.set_active(Some(BAUD_RATES.len() as u32)),
+        None => set_entry_text(baud_combobox, &baud_rate.to_string()),
+    }
+}
+
+fn set_entry_text(combobox: &gtk::ComboBoxText, text: &str) {
+    if let Some(entry) = combobox
+        .get_child()
+        .and_then(|child| child.downcast::<gtk::Entry>().ok())
+    {
+        entry.set_text(text);
+    }
+}
+
+/// Find avaible ports
+fn get_ports() -> Result<Vec<serialport::SerialPortInfo>, String> {
+    serialport::available_ports().map_err(|err| format!("Cannot list the ports: {}", err))
+}
+
+/// Show the warning icon with the error as tooltip, or hide it
+fn set_port_warning(port_warning: &gtk::Image, error: Option<&str>) {
+    port_warning.set_tooltip_text(error);
+    port_warning.set_visible(error.is_some());
 }
 
 /// Creates the thread with all channels that handles the connection to the printer
 fn create_connection_thread(
     connection_string: String,
     port_settings: SerialPortSettings,
+    handshake_timeout: std::time::Duration,
     stream: relm::EventStream<Msg>,
-) -> Result<
-    (
-        std::sync::mpsc::Sender<ThreadCmd>,
-        std::thread::JoinHandle<()>,
-    ),
-    (),
-> {
+) -> (
+    std::sync::mpsc::Sender<ThreadCmd>,
+    std::thread::JoinHandle<()>,
+) {
     // Create Channel from and to thread
     let (_channel, sender) = relm::Channel::new(move |msg: ThreadStatus| {
         match msg {
             ThreadStatus::ConnectionError => stream.emit(Msg::Disconnect),
+            ThreadStatus::ConnectionFailed(error) => stream.emit(Msg::ConnectionFailed(error)),
             ThreadStatus::RecivedLine(line) => stream.emit(Msg::ReciveLine(line)),
             ThreadStatus::ConnectionActive => stream.emit(Msg::ConnectionActive),
+            ThreadStatus::BaudRateDetected(baud_rate) => {
+                stream.emit(Msg::BaudRateDetected(baud_rate))
+            }
         };
     });
 
     let (mpsc_tx, mpsc_rx) = std::sync::mpsc::channel::<ThreadCmd>();
 
-    if let Ok(mut port) = serialport::open_with_settings(&connection_string, &port_settings) {
-        let thread_handle = std::thread::spawn(move || {
-            // After thread start wait some time
-            let connection_active_time =
-                std::time::Instant::now() + std::time::Duration::from_secs(10);
-            let mut connected = false;
-            // Read data from port in an endless loop
-            let mut buffer = vec![0; 512];
-            let mut line = String::new();
-            loop {
-                // Check for connection
-                if connected {
-                    // Read Command
-                    match mpsc_rx.try_recv() {
-                        Ok(cmd) => match cmd {
-                            ThreadCmd::Disconnect => break,
-                            ThreadCmd::SendLine(line) => {
-                                if port.write_all(line.as_ref()).is_err() {
-                                    break;
-                                }
-                            }
-                        },
-                        Err(std::sync::mpsc::TryRecvError::Disconnected) => break,
-                        Err(std::sync::mpsc::TryRecvError::Empty) => (),
-                    }
-                } else if connection_active_time < std::time::Instant::now() {
-                    connected = true;
-                    sender.send(ThreadStatus::ConnectionActive).unwrap();
+    // The port is opened by the thread, connecting to a network bridge may take a while
+    let thread_handle = std::thread::spawn(move || {
+        // Network bridges take the baud rate from their own settings or RFC 2217
+        let detect = port_settings.baud_rate == 0 && transport::is_serial(&connection_string);
+        let mut transport = if detect {
+            let detected = match detect_baud_rate(&connection_string, port_settings, &mpsc_rx) {
+                Ok(detected) => detected,
+                Err(_) => {
+                    sender.send(ThreadStatus::ConnectionError).ok();
+                    return;
                 }
+            };
+            sender
+                .send(ThreadStatus::BaudRateDetected(
+                    detected.as_ref().map(|(_, baud_rate)| *baud_rate),
+                ))
+                .ok();
+            match detected {
+                Some((transport, _)) => transport,
+                None => {
+                    sender
+                        .send(ThreadStatus::ConnectionFailed(format!(
+                            "{}: No answer at any baud rate",
+                            connection_string
+                        )))
+                        .ok();
+                    sender.send(ThreadStatus::ConnectionError).ok();
+                    return;
+                }
+            }
+        } else {
+            let mut transport = transport::for_target(&connection_string, port_settings);
+            if let Err(error) = transport.open() {
+                sender.send(ThreadStatus::ConnectionFailed(error)).ok();
+                sender.send(ThreadStatus::ConnectionError).ok();
+                return;
+            }
+            transport
+        };
+        let mut lines = LineBuffer::default();
+        if let Err(stop) = handshake(
+            &mut *transport,
+            handshake_timeout,
+            &mut lines,
+            &sender,
+            &mpsc_rx,
+        ) {
+            transport.close();
+            if let Stop::Failed(error) = stop {
+                sender.send(ThreadStatus::ConnectionFailed(error)).ok();
+            }
+            sender.send(ThreadStatus::ConnectionError).ok();
+            return;
+        }
+        sender.send(ThreadStatus::ConnectionActive).ok();
 
-                // Try to read a line
-                match port.read(&mut buffer) {
-                    Ok(n) if n > 0 => {
-                        for &c in &buffer {
-                            if c != 0x0a {
-                                if c.is_ascii_alphanumeric()
-                                    | c.is_ascii_punctuation()
-                                    | c.is_ascii_whitespace()
-                                {
-                                    line.push(c.into());
-                                }
-                            } else {
-                                sender
-                                    .send(ThreadStatus::RecivedLine(line.clone()))
-                                    .unwrap();
-                                line.clear();
-                            };
+        // Read data from port in an endless loop
+        let mut buffer = vec![0; 512];
+        loop {
+            // Read Command
+            match mpsc_rx.try_recv() {
+                Ok(cmd) => match cmd {
+                    ThreadCmd::Disconnect => break,
+                    ThreadCmd::SendLine(line) => {
+                        if transport.write(line.as_ref()).is_err() {
+                            break;
                         }
-                        buffer = vec![0; 512];
                     }
+                },
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => break,
+                Err(std::sync::mpsc::TryRecvError::Empty) => (),
+            }
+
+            // Try to read a line
+            match transport.read(&mut buffer) {
+                Ok(n) => {
+                    for line in lines.push(&buffer[..n]) {
+                        sender.send(ThreadStatus::RecivedLine(line)).ok();
+                    }
+                }
+                Err(err) => match err.kind() {
+                    std::io::ErrorKind::TimedOut => (),
+                    _ => {
+                        sender
+                            .send(ThreadStatus::ConnectionFailed(format!(
+                                "{}: {}",
+                                transport.describe(),
+                                err
+                            )))
+                            .ok();
+                        break;
+                    }
+                },
+            };
+        }
+        transport.close();
+        sender.send(ThreadStatus::ConnectionError).ok();
+    });
+    (mpsc_tx, thread_handle)
+}
 
-                    Ok(_) => (),
+/// Splits the received bytes into lines, leaving out what is not printable ASCII
+#[derive(Default)]
+struct LineBuffer {
+    line: String,
+}
 
-                    Err(err) => match err.kind() {
-                        std::io::ErrorKind::TimedOut => (),
-                        _ => {
-                            println!("{:?}", err);
-                            break;
-                        }
-                    },
-                };
+impl LineBuffer {
+    /// The lines completed by the bytes
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &c in bytes {
+            if c == b'\n' {
+                lines.push(std::mem::take(&mut self.line));
+            } else if c.is_ascii_alphanumeric() | c.is_ascii_punctuation() | c.is_ascii_whitespace()
+            {
+                self.line.push(c.into());
             }
-            sender.send(ThreadStatus::ConnectionError).ok();
-        });
-        Ok((mpsc_tx, thread_handle))
-    } else {
-        Err(())
+        }
+        lines
+    }
+}
+
+/// True if the connection thread has to end, checked while it waits for the firmware
+fn disconnect_requested(commands: &std::sync::mpsc::Receiver<ThreadCmd>) -> bool {
+    loop {
+        match commands.try_recv() {
+            Ok(ThreadCmd::Disconnect) | Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                return true
+            }
+            // Nothing is sent before the connection is active
+            Ok(ThreadCmd::SendLine(_)) => (),
+            Err(std::sync::mpsc::TryRecvError::Empty) => return false,
+        }
+    }
+}
+
+/// Send `M110 N0` until the firmware answers with an ok. Boards that reset when the port is
+/// opened lose what was sent before their `start`, so they are probed again right after it.
+/// The oks of the other probes are waited for, the command queue would take them as answers
+/// to its own lines.
+fn handshake(
+    transport: &mut dyn Transport,
+    timeout: std::time::Duration,
+    lines: &mut LineBuffer,
+    sender: &relm::Sender<ThreadStatus>,
+    commands: &std::sync::mpsc::Receiver<ThreadCmd>,
+) -> Result<(), Stop> {
+    let failed = |transport: &dyn Transport, err: std::io::Error| {
+        Stop::Failed(format!("{}: {}", transport.describe(), err))
+    };
+    let deadline = std::time::Instant::now() + timeout;
+    // Give the banner of a board without reset a moment to arrive
+    let mut next_probe = std::time::Instant::now() + HANDSHAKE_DELAY;
+    // Probes sent and answered since the last start
+    let mut probes = 0;
+    let mut answers = 0;
+    let mut last_answer: Option<std::time::Instant> = None;
+    let mut buffer = [0; 256];
+    loop {
+        if disconnect_requested(commands) {
+            return Err(Stop::Disconnect);
+        }
+        let now = std::time::Instant::now();
+        match last_answer {
+            // Probes sent while the board was starting up are never answered
+            Some(last_answer) if answers >= probes || now - last_answer >= HANDSHAKE_QUIET => {
+                return Ok(())
+            }
+            Some(_) => (),
+            None if now >= deadline => {
+                return Err(Stop::Failed(format!(
+                    "{}: The firmware did not answer within {} s, \
+                     check the port and the baud rate",
+                    transport.describe(),
+                    timeout.as_secs()
+                )))
+            }
+            None if now >= next_probe => {
+                transport
+                    .write(b"M110 N0\n")
+                    .map_err(|err| failed(transport, err))?;
+                probes += 1;
+                next_probe = now + HANDSHAKE_INTERVAL;
+            }
+            None => (),
+        }
+        let n = match transport.read(&mut buffer) {
+            Ok(n) => n,
+            Err(ref err) if err.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(failed(transport, err)),
+        };
+        for line in lines.push(&buffer[..n]) {
+            let response = response::Response::parse(&line);
+            sender.send(ThreadStatus::RecivedLine(line)).ok();
+            match response {
+                response::Response::Ok(_) => {
+                    answers += 1;
+                    last_answer = Some(std::time::Instant::now());
+                }
+                response::Response::Start => {
+                    probes = 0;
+                    answers = 0;
+                    last_answer = None;
+                    next_probe = std::time::Instant::now();
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Open the port at every baud rate until the firmware answers.
+/// Fails only if Disconnect was clicked.
+fn detect_baud_rate(
+    connection_string: &str,
+    mut port_settings: SerialPortSettings,
+    commands: &std::sync::mpsc::Receiver<ThreadCmd>,
+) -> Result<Option<(Box<dyn Transport>, u32)>, Stop> {
+    for &baud_rate in AUTO_BAUD_RATES.iter() {
+        port_settings.baud_rate = baud_rate;
+        let mut transport = SerialTransport::new(connection_string, port_settings);
+        // Some adapters do not support every rate
+        if transport.open().is_err() {
+            continue;
+        }
+        let answered = probe(&mut transport, commands);
+        if let Ok(true) = answered {
+            return Ok(Some((Box::new(transport), baud_rate)));
+        }
+        transport.close();
+        answered?;
+    }
+    Ok(None)
+}
+
+/// Send `M110` and `M115` until a well-formed answer comes back, then wait until the
+/// answers to the other probes arrived so the handshake does not take them as its own
+fn probe(
+    transport: &mut dyn Transport,
+    commands: &std::sync::mpsc::Receiver<ThreadCmd>,
+) -> Result<bool, Stop> {
+    let deadline = std::time::Instant::now() + PROBE_TIME;
+    let mut next_probe = std::time::Instant::now();
+    let mut answered = false;
+    let mut last_received = std::time::Instant::now();
+    let mut received = Vec::new();
+    let mut buffer = [0; 256];
+    loop {
+        if disconnect_requested(commands) {
+            return Err(Stop::Disconnect);
+        }
+        let now = std::time::Instant::now();
+        if answered {
+            if now - last_received >= HANDSHAKE_QUIET {
+                return Ok(true);
+            }
+        } else if now >= deadline {
+            return Ok(false);
+        } else if now >= next_probe {
+            if transport.write(b"M110 N0\nM115\n").is_err() {
+                return Ok(false);
+            }
+            next_probe = now + std::time::Duration::from_secs(1);
+        }
+        match transport.read(&mut buffer) {
+            Ok(0) => (),
+            // Only the time of the last answer matters once the firmware answered
+            Ok(_) if answered => last_received = std::time::Instant::now(),
+            Ok(n) => received.extend_from_slice(&buffer[..n]),
+            Err(ref err) if err.kind() == std::io::ErrorKind::TimedOut => (),
+            Err(_) => return Ok(false),
+        }
+        // At the wrong baud rate only garbage arrives
+        while let Some(end) = received.iter().position(|&c| c == b'\n') {
+            let line: Vec<u8> = received.drain(..=end).collect();
+            if !line.is_ascii() {
+                continue;
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            answered = match response::Response::parse(line) {
+                response::Response::Ok(_) | response::Response::Start => true,
+                _ => line.contains("FIRMWARE_NAME"),
+            };
+            if answered {
+                last_received = std::time::Instant::now();
+                received.clear();
+                break;
+            }
+        }
     }
 }
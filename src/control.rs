@@ -1,23 +1,53 @@
 use gtk::prelude::*;
-use relm::Relm;
+use relm::{connect, Relm};
 use relm_derive::Msg;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::dialect;
+use crate::gcode;
+use crate::report;
+
+/// A reported position older than this is not trusted to keep jog moves inside the build volume
+const POSITION_MAX_AGE: Duration = Duration::from_secs(3);
 
 #[derive(Debug, Msg)]
 pub enum Msg {
-    SendCmd(String),
-    //MoveX(f32),
-    //MoveY(f32),
-    //MoveZ(f32),
-    //MoveE1(f32),
-    //MoveE2(f32),
+    SendCmd(gcode::Line),
+    /// Move an axis by one step in the given direction
+    Jog(char, f32),
+    /// Move the filament of an extruder by one step in the given direction
+    Extrude(u32, f32),
+    Home(char),
+    Tick,
+    SetConfig(config::Config),
+    SetProfile(config::Profile),
     GetTemperature,
-    SetTemperature(String),
-    SetPosition(String),
+    SetTemperature(report::TemperatureReport),
+    SetPosition(report::PositionReport),
     GetPosition,
+    /// The firmware reports the temperature and the position on its own, no need to poll
+    SetAutoReport(bool, bool),
+    /// The firmware of the printer, from the profile or detected
+    SetFlavour(config::Flavour),
+    EmergencyStop,
 }
 
 pub struct Model {
     relm: relm::Relm<Widget>,
+    polling: config::PollingConfig,
+    jog: config::JogConfig,
+    /// Jog moves stay inside its build volume
+    profile: config::Profile,
+    /// Selects the tool before extruding
+    dialect: Box<dyn dialect::Dialect>,
+    /// The last reported position and when it arrived
+    position: Option<([f32; 3], Instant)>,
+    last_temperature_poll: Instant,
+    last_position_poll: Instant,
+    auto_report_temperature: bool,
+    auto_report_position: bool,
 }
 
 struct GtkWidgets {
@@ -25,8 +55,19 @@ struct GtkWidgets {
     label_x_pos: gtk::Label,
     label_y_pos: gtk::Label,
     label_z_pos: gtk::Label,
-    label_e1_temp: gtk::Label,
-    label_bed_temp: gtk::Label,
+    label_x_count: gtk::Label,
+    label_y_count: gtk::Label,
+    label_z_count: gtk::Label,
+    btn_e2_neg: gtk::Button,
+    btn_e2_pos: gtk::Button,
+    grid_temp: gtk::Grid,
+    temperature_rows: BTreeMap<report::Heater, TemperatureRow>,
+}
+
+/// The labels of one heater in the temperature grid
+struct TemperatureRow {
+    label_temp: gtk::Label,
+    label_power: gtk::Label,
 }
 
 pub struct Widget {
@@ -36,33 +77,203 @@ pub struct Widget {
 
 impl relm::Update for Widget {
     type Model = Model;
-    type ModelParam = ();
+    type ModelParam = config::Config;
     type Msg = Msg;
 
     fn subscriptions(&mut self, relm: &Relm<Self>) {
-        relm::interval(relm.stream(), 1000, || Msg::GetTemperature);
-        relm::interval(relm.stream(), 1000, || Msg::GetPosition);
+        // The polling intervals can be changed at any time
+        relm::interval(relm.stream(), 100, || Msg::Tick);
     }
 
-    fn model(relm: &Relm<Self>, _param: Self::ModelParam) -> Self::Model {
-        Model { relm: relm.clone() }
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
+        Model {
+            relm: relm.clone(),
+            polling: config.polling,
+            jog: config.jog,
+            profile: config.profile().clone(),
+            dialect: dialect::for_flavour(config.profile().flavour),
+            position: None,
+            last_temperature_poll: Instant::now(),
+            last_position_poll: Instant::now(),
+            auto_report_temperature: false,
+            auto_report_position: false,
+        }
     }
 
     fn update(&mut self, event: Self::Msg) {
         match event {
+            Msg::Tick => {
+                let now = Instant::now();
+                if !self.model.auto_report_temperature
+                    && poll_due(
+                        &mut self.model.last_temperature_poll,
+                        self.model.polling.temperature,
+                        now,
+                    )
+                {
+                    self.update(Msg::GetTemperature);
+                }
+                if !self.model.auto_report_position
+                    && poll_due(
+                        &mut self.model.last_position_poll,
+                        self.model.polling.position,
+                        now,
+                    )
+                {
+                    self.update(Msg::GetPosition);
+                }
+            }
+            Msg::SetConfig(config) => {
+                self.model.polling = config.polling;
+                self.model.jog = config.jog;
+            }
+            Msg::SetProfile(profile) => {
+                let dual = profile.extruders > 1;
+                self.widgets.btn_e2_neg.set_sensitive(dual);
+                self.widgets.btn_e2_pos.set_sensitive(dual);
+                // Start over with the heaters of the printer, others are added once reported
+                for _ in 0..self.widgets.temperature_rows.len() {
+                    self.widgets.grid_temp.remove_row(0);
+                }
+                self.widgets.temperature_rows.clear();
+                for (row, heater) in profile_heaters(&profile).into_iter().enumerate() {
+                    self.widgets.grid_temp.insert_row(row as i32);
+                    let temperature_row =
+                        create_temperature_row(&self.widgets.grid_temp, row as i32, heater);
+                    self.widgets
+                        .temperature_rows
+                        .insert(heater, temperature_row);
+                }
+                self.widgets.grid_temp.show_all();
+                self.model.profile = profile;
+            }
+            Msg::Jog(axis, direction) => {
+                let jog = self.model.jog;
+                let (index, step, feedrate) = match axis {
+                    'X' => (0, jog.xy_step, jog.xy_feedrate),
+                    'Y' => (1, jog.xy_step, jog.xy_feedrate),
+                    _ => (2, jog.z_step, jog.z_feedrate),
+                };
+                let mut distance = step * direction;
+                // Stop at the edges of the build volume while the position is known.
+                // An old report is not trusted, the console or a job may have moved the head
+                // since then. Until the next report the jog moves are followed.
+                if let Some((ref mut position, ref reported)) = self.model.position {
+                    if reported.elapsed() < POSITION_MAX_AGE {
+                        let target = (position[index] + distance)
+                            .max(self.model.profile.axis_min[index])
+                            .min(self.model.profile.build_volume[index]);
+                        distance = target - position[index];
+                        if distance.abs() < 0.001 {
+                            return;
+                        }
+                        position[index] = target;
+                    }
+                }
+                let stream = self.model.relm.stream();
+                // Relative move, then back to absolute positioning
+                stream.emit(Msg::SendCmd(gcode::Line::command('G', 91)));
+                stream.emit(Msg::SendCmd(
+                    gcode::Line::command('G', 1)
+                        .with(axis, f64::from(distance))
+                        .with('F', f64::from(feedrate)),
+                ));
+                stream.emit(Msg::SendCmd(gcode::Line::command('G', 90)));
+            }
+            Msg::Extrude(tool, direction) => {
+                let jog = self.model.jog;
+                let stream = self.model.relm.stream();
+                stream.emit(Msg::SendCmd(self.model.dialect.select_tool(tool)));
+                stream.emit(Msg::SendCmd(gcode::Line::command('G', 91)));
+                stream.emit(Msg::SendCmd(
+                    gcode::Line::command('G', 1)
+                        .with('E', f64::from(jog.e_step * direction))
+                        .with('F', f64::from(jog.e_feedrate)),
+                ));
+                stream.emit(Msg::SendCmd(gcode::Line::command('G', 90)));
+            }
+            Msg::Home(axis) => {
+                let mut line = gcode::Line::command('G', 28);
+                line.parameters.push(gcode::Parameter {
+                    letter: axis,
+                    value: None,
+                    string: None,
+                });
+                self.model.relm.stream().emit(Msg::SendCmd(line));
+            }
             Msg::GetPosition => self
                 .model
                 .relm
                 .stream()
-                .emit(Msg::SendCmd("M114".to_string())),
+                .emit(Msg::SendCmd(gcode::Line::command('M', 114))),
             Msg::GetTemperature => self
                 .model
                 .relm
                 .stream()
-                .emit(Msg::SendCmd("M105".to_string())),
+                .emit(Msg::SendCmd(gcode::Line::command('M', 105))),
             Msg::SendCmd(_cmd) => (),
-            Msg::SetPosition(response) => (),
-            Msg::SetTemperature(response) => (),
+            Msg::SetAutoReport(temperature, position) => {
+                self.model.auto_report_temperature = temperature;
+                self.model.auto_report_position = position;
+            }
+            Msg::SetFlavour(flavour) => self.model.dialect = dialect::for_flavour(flavour),
+            Msg::EmergencyStop => (),
+            Msg::SetPosition(report) => {
+                self.model.position = Some(([report.x, report.y, report.z], Instant::now()));
+                self.widgets
+                    .label_x_pos
+                    .set_text(&format!("{:.2}", report.x));
+                self.widgets
+                    .label_y_pos
+                    .set_text(&format!("{:.2}", report.y));
+                self.widgets
+                    .label_z_pos
+                    .set_text(&format!("{:.2}", report.z));
+                if let Some([x, y, z]) = report.count {
+                    self.widgets
+                        .label_x_count
+                        .set_text(&format!("{:.0} steps", x));
+                    self.widgets
+                        .label_y_count
+                        .set_text(&format!("{:.0} steps", y));
+                    self.widgets
+                        .label_z_count
+                        .set_text(&format!("{:.0} steps", z));
+                }
+            }
+            Msg::SetTemperature(report) => {
+                for (heater, reading) in report.heaters.iter() {
+                    if !self.widgets.temperature_rows.contains_key(heater) {
+                        // Keep the rows sorted like the heaters
+                        let row = self
+                            .widgets
+                            .temperature_rows
+                            .keys()
+                            .filter(|&known| known < heater)
+                            .count() as i32;
+                        self.widgets.grid_temp.insert_row(row);
+                        let temperature_row =
+                            create_temperature_row(&self.widgets.grid_temp, row, *heater);
+                        self.widgets.grid_temp.show_all();
+                        self.widgets
+                            .temperature_rows
+                            .insert(*heater, temperature_row);
+                    }
+
+                    // Safe to unwrap because the row was created above
+                    let temperature_row = self.widgets.temperature_rows.get(heater).unwrap();
+                    temperature_row.label_temp.set_text(&match reading.target {
+                        Some(target) => format!("{:.1} / {:.1}", reading.actual, target),
+                        None => format!("{:.1}", reading.actual),
+                    });
+                    temperature_row.label_power.set_text(
+                        &reading
+                            .power_percent()
+                            .map(|power| format!("{:.0}%", power))
+                            .unwrap_or_default(),
+                    );
+                }
+            }
         }
     }
 }
@@ -74,10 +285,25 @@ impl relm::Widget for Widget {
         self.widgets.root.clone()
     }
 
-    fn view(_relm: &Relm<Self>, model: Self::Model) -> Self {
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
         // The root widget
         let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
 
+        // Stops the printer right away, it has to be reset afterwards
+        let btn_emergency_stop = gtk::Button::with_label("Emergency stop");
+        btn_emergency_stop
+            .get_style_context()
+            .add_class("destructive-action");
+        btn_emergency_stop.set_tooltip_text(Some("Send M112, the printer halts immediately"));
+        btn_emergency_stop.set_halign(gtk::Align::End);
+        vbox.pack_start(&btn_emergency_stop, false, false, 5);
+        connect!(
+            relm,
+            btn_emergency_stop,
+            connect_clicked(_),
+            Msg::EmergencyStop
+        );
+
         // Create all UI Elements for manual control
         let btn_x_neg = gtk::Button::with_label("X-");
         let btn_x_pos = gtk::Button::with_label("X+");
@@ -89,6 +315,8 @@ impl relm::Widget for Widget {
         let btn_e1_pos = gtk::Button::with_label("E1+");
         let btn_e2_neg = gtk::Button::with_label("E2-");
         let btn_e2_pos = gtk::Button::with_label("E2+");
+        btn_e2_neg.set_sensitive(model.profile.extruders > 1);
+        btn_e2_pos.set_sensitive(model.profile.extruders > 1);
         let btn_x_home = gtk::Button::with_label("Home X");
         let btn_y_home = gtk::Button::with_label("Home Y");
         let btn_z_home = gtk::Button::with_label("Home Z");
@@ -124,20 +352,28 @@ impl relm::Widget for Widget {
 
         vbox.pack_start(&grid, false, false, 20);
 
+        connect!(relm, btn_x_neg, connect_clicked(_), Msg::Jog('X', -1.0));
+        connect!(relm, btn_x_pos, connect_clicked(_), Msg::Jog('X', 1.0));
+        connect!(relm, btn_y_neg, connect_clicked(_), Msg::Jog('Y', -1.0));
+        connect!(relm, btn_y_pos, connect_clicked(_), Msg::Jog('Y', 1.0));
+        connect!(relm, btn_z_neg, connect_clicked(_), Msg::Jog('Z', -1.0));
+        connect!(relm, btn_z_pos, connect_clicked(_), Msg::Jog('Z', 1.0));
+        connect!(relm, btn_e1_neg, connect_clicked(_), Msg::Extrude(0, -1.0));
+        connect!(relm, btn_e1_pos, connect_clicked(_), Msg::Extrude(0, 1.0));
+        connect!(relm, btn_e2_neg, connect_clicked(_), Msg::Extrude(1, -1.0));
+        connect!(relm, btn_e2_pos, connect_clicked(_), Msg::Extrude(1, 1.0));
+        connect!(relm, btn_x_home, connect_clicked(_), Msg::Home('X'));
+        connect!(relm, btn_y_home, connect_clicked(_), Msg::Home('Y'));
+        connect!(relm, btn_z_home, connect_clicked(_), Msg::Home('Z'));
+
         // The Status widget
 
-        // A Grid for the Position (2x3)
+        // A Grid for the Position with the logical position and the stepper counts
         let grid_pos = gtk::Grid::new();
-        for _ in 0..3 {
+        for _ in 0..4 {
             grid_pos.insert_column(0);
         }
 
-        for label in ["X:", "Y:", "Z:"].iter() {
-            grid_pos.insert_row(0);
-            grid_pos.attach(&gtk::Label::new(Some(label)), 0, 0, 1, 1);
-            grid_pos.attach(&gtk::Label::new(Some("mm")), 2, 0, 1, 1);
-        }
-
         let label_x_pos = gtk::Label::new(Some("0.0"));
         label_x_pos.set_property_width_request(100);
         let label_y_pos = gtk::Label::new(Some("0.0"));
@@ -145,29 +381,44 @@ impl relm::Widget for Widget {
         let label_z_pos = gtk::Label::new(Some("0.0"));
         label_z_pos.set_property_width_request(100);
 
-        grid_pos.attach(&label_x_pos, 1, 0, 1, 1);
-        grid_pos.attach(&label_y_pos, 1, 1, 1, 1);
-        grid_pos.attach(&label_z_pos, 1, 2, 1, 1);
+        let label_x_count = gtk::Label::new(None);
+        label_x_count.set_property_width_request(100);
+        let label_y_count = gtk::Label::new(None);
+        label_y_count.set_property_width_request(100);
+        let label_z_count = gtk::Label::new(None);
+        label_z_count.set_property_width_request(100);
 
-        // A Grid for the Temperature
+        for (row, (label, label_pos, label_count)) in [
+            ("X:", &label_x_pos, &label_x_count),
+            ("Y:", &label_y_pos, &label_y_count),
+            ("Z:", &label_z_pos, &label_z_count),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let row = row as i32;
+            grid_pos.insert_row(row);
+            grid_pos.attach(&gtk::Label::new(Some(label)), 0, row, 1, 1);
+            grid_pos.attach(*label_pos, 1, row, 1, 1);
+            grid_pos.attach(&gtk::Label::new(Some("mm")), 2, row, 1, 1);
+            grid_pos.attach(*label_count, 3, row, 1, 1);
+        }
+
+        // A Grid for the Temperature, further rows are added for every heater the firmware reports
         let grid_temp = gtk::Grid::new();
-        for _ in 0..3 {
+        for _ in 0..4 {
             grid_temp.insert_column(0);
         }
 
-        for label in ["E1:", "Bed:"].iter() {
-            grid_temp.insert_row(0);
-            grid_temp.attach(&gtk::Label::new(Some(label)), 0, 0, 1, 1);
-            grid_temp.attach(&gtk::Label::new(Some("°C")), 2, 0, 1, 1);
+        let mut temperature_rows = BTreeMap::new();
+        for (row, heater) in profile_heaters(&model.profile).into_iter().enumerate() {
+            grid_temp.insert_row(row as i32);
+            temperature_rows.insert(
+                heater,
+                create_temperature_row(&grid_temp, row as i32, heater),
+            );
         }
 
-        let label_e1_temp = gtk::Label::new(Some("0.0"));
-        label_e1_temp.set_property_width_request(100);
-        let label_bed_temp = gtk::Label::new(Some("0.0"));
-        label_bed_temp.set_property_width_request(100);
-        grid_temp.attach(&label_e1_temp, 1, 0, 1, 1);
-        grid_temp.attach(&label_bed_temp, 1, 1, 1, 1);
-
         // Box to hold the Status
         let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 3);
         hbox.pack_start(&grid_pos, false, false, 50);
@@ -182,9 +433,61 @@ impl relm::Widget for Widget {
                 label_x_pos,
                 label_y_pos,
                 label_z_pos,
-                label_e1_temp,
-                label_bed_temp,
+                label_x_count,
+                label_y_count,
+                label_z_count,
+                btn_e2_neg,
+                btn_e2_pos,
+                grid_temp,
+                temperature_rows,
             },
         }
     }
 }
+
+/// The heaters a printer has according to its profile
+fn profile_heaters(profile: &config::Profile) -> Vec<report::Heater> {
+    let mut heaters: Vec<report::Heater> = (0..profile.extruders.max(1) as usize)
+        .map(report::Heater::Hotend)
+        .collect();
+    if profile.heated_bed {
+        heaters.push(report::Heater::Bed);
+    }
+    if profile.heated_chamber {
+        heaters.push(report::Heater::Chamber);
+    }
+    heaters
+}
+
+/// True if the interval passed since the last poll, which then becomes now
+fn poll_due(last_poll: &mut Instant, interval: u64, now: Instant) -> bool {
+    if interval == 0 || now.duration_since(*last_poll) < Duration::from_millis(interval) {
+        return false;
+    }
+    *last_poll = now;
+    true
+}
+
+/// Adds the labels for a heater to the given (already inserted) row of the temperature grid
+fn create_temperature_row(grid: &gtk::Grid, row: i32, heater: report::Heater) -> TemperatureRow {
+    let label_temp = gtk::Label::new(Some("0.0"));
+    label_temp.set_property_width_request(100);
+    let label_power = gtk::Label::new(None);
+    label_power.set_property_width_request(50);
+
+    grid.attach(
+        &gtk::Label::new(Some(heater.label().as_str())),
+        0,
+        row,
+        1,
+        1,
+    );
+    grid.attach(&label_temp, 1, row, 1, 1);
+    grid.attach(&gtk::Label::new(Some("°C")), 2, row, 1, 1);
+    grid.attach(&label_power, 3, row, 1, 1);
+
+    TemperatureRow {
+        label_temp,
+        label_power,
+    }
+}
@@ -0,0 +1,217 @@
+use crate::config::Flavour;
+use crate::firmware;
+use crate::gcode;
+
+/// What differs between the firmwares beyond the G-code they all understand.
+/// The rest of the application speaks Marlin, a dialect translates where needed.
+pub trait Dialect {
+    /// Sent once the firmware is known, after `M115`
+    fn queries(&self) -> Vec<gcode::Line> {
+        Vec::new()
+    }
+
+    /// Rewrite a received line to the Marlin format understood by `response` and `report`
+    fn normalize(&self, line: &str) -> String {
+        line.to_string()
+    }
+
+    /// The answer to `M114` comes with the ok instead of on its own line
+    fn position_in_ok(&self) -> bool {
+        false
+    }
+
+    /// Make the tool active, e.g. before extruding
+    fn select_tool(&self, tool: u32) -> gcode::Line {
+        gcode::Line::command('T', tool)
+    }
+
+    /// `M112` is handled as soon as it arrives, even with commands waiting in the buffer
+    fn immediate_emergency_stop(&self, _capabilities: &firmware::Capabilities) -> bool {
+        true
+    }
+
+    /// How to get the printer going again after an emergency stop
+    fn emergency_stop_recovery(&self) -> &'static str {
+        "Send M999 to continue"
+    }
+}
+
+/// The dialect of a firmware, Marlin until a detected one is known
+pub fn for_flavour(flavour: Flavour) -> Box<dyn Dialect> {
+    match flavour {
+        Flavour::Auto | Flavour::Marlin => Box::new(Marlin),
+        Flavour::RepRapFirmware => Box::new(RepRapFirmware),
+        Flavour::Klipper => Box::new(Klipper),
+        Flavour::Smoothieware => Box::new(Smoothieware),
+        Flavour::Prusa => Box::new(Prusa),
+    }
+}
+
+/// The firmware by the `FIRMWARE_NAME` of its answer to `M115`
+pub fn detect(firmware_name: &str) -> Option<Flavour> {
+    let name = firmware_name.to_lowercase();
+    // Prusa firmware is "based on Marlin"
+    if name.contains("prusa") {
+        Some(Flavour::Prusa)
+    } else if name.contains("marlin") {
+        Some(Flavour::Marlin)
+    } else if name.contains("reprapfirmware") {
+        Some(Flavour::RepRapFirmware)
+    } else if name.contains("klipper") {
+        Some(Flavour::Klipper)
+    } else if name.contains("smoothie") {
+        Some(Flavour::Smoothieware)
+    } else {
+        None
+    }
+}
+
+struct Marlin;
+
+impl Dialect for Marlin {
+    /// The limits for the print time estimate
+    fn queries(&self) -> Vec<gcode::Line> {
+        vec![gcode::Line::command('M', 503)]
+    }
+
+    /// Without the emergency parser `M112` waits behind the buffered commands
+    fn immediate_emergency_stop(&self, capabilities: &firmware::Capabilities) -> bool {
+        capabilities.has(firmware::EMERGENCY_PARSER)
+    }
+
+    fn emergency_stop_recovery(&self) -> &'static str {
+        "Reset the printer to continue"
+    }
+}
+
+/// Marlin based, the emergency stop is always handled right away
+struct Prusa;
+
+impl Dialect for Prusa {
+    fn queries(&self) -> Vec<gcode::Line> {
+        vec![gcode::Line::command('M', 503)]
+    }
+
+    fn emergency_stop_recovery(&self) -> &'static str {
+        "Reset the printer to continue"
+    }
+}
+
+/// `M503` lists `config.g` with feedrates in mm/min, so the limits are not read
+struct RepRapFirmware;
+
+impl Dialect for RepRapFirmware {}
+
+/// The G-code interface of Klippy on its pseudo terminal, e.g. `/tmp/printer`
+struct Klipper;
+
+impl Dialect for Klipper {
+    /// `T0` and `T1` only exist if the config defines them as macros
+    fn select_tool(&self, tool: u32) -> gcode::Line {
+        let extruder = if tool == 0 {
+            "extruder".to_string()
+        } else {
+            format!("extruder{}", tool)
+        };
+        gcode::Line {
+            text: Some(format!("ACTIVATE_EXTRUDER EXTRUDER={}", extruder)),
+            ..gcode::Line::default()
+        }
+    }
+
+    fn emergency_stop_recovery(&self) -> &'static str {
+        "Send FIRMWARE_RESTART to continue"
+    }
+}
+
+struct Smoothieware;
+
+impl Dialect for Smoothieware {
+    /// The power follows each heater as `@<pwm>`: `T:21.2 /0.0 @0 B:21.0 /0.0 @0`.
+    /// The PWM goes up to 255, it is scaled to the 127 of Marlin.
+    fn normalize(&self, line: &str) -> String {
+        let mut heater = "";
+        let words: Vec<String> = line
+            .split_whitespace()
+            .map(|word| {
+                if let Some(key) = word.find(':').map(|pos| &word[..pos]) {
+                    heater = key;
+                }
+                match word
+                    .strip_prefix('@')
+                    .and_then(|pwm| pwm.parse::<u32>().ok())
+                {
+                    Some(pwm) => {
+                        let power = (pwm.min(255) * 127 + 127) / 255;
+                        match heater {
+                            "T" => format!("@:{}", power),
+                            _ => format!("{}@:{}", heater, power),
+                        }
+                    }
+                    _ => word.to_string(),
+                }
+            })
+            .collect();
+        words.join(" ")
+    }
+
+    /// `ok C: X:0.0000 Y:0.0000 Z:0.0000 E:0.0000`
+    fn position_in_ok(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report;
+
+    #[test]
+    fn detection() {
+        assert_eq!(detect("Marlin 2.0.9.3 (Github)"), Some(Flavour::Marlin));
+        assert_eq!(
+            detect("Prusa-Firmware 3.10.1 based on Marlin"),
+            Some(Flavour::Prusa)
+        );
+        assert_eq!(
+            detect("RepRapFirmware for Duet 3 MB6HC"),
+            Some(Flavour::RepRapFirmware)
+        );
+        assert_eq!(detect("Klipper"), Some(Flavour::Klipper));
+        assert_eq!(detect("Smoothieware,"), Some(Flavour::Smoothieware));
+        assert_eq!(detect("Repetier_1.0.4"), None);
+    }
+
+    #[test]
+    fn smoothieware_reports() {
+        let dialect = for_flavour(Flavour::Smoothieware);
+        let line = dialect.normalize("ok T:210.1 /210.0 @87 B:60.0 /60.0 @255");
+        assert_eq!(line, "ok T:210.1 /210.0 @:43 B:60.0 /60.0 B@:127");
+        let report = report::TemperatureReport::parse(&line[3..]).unwrap();
+        let bed = &report.heaters[&report::Heater::Bed];
+        assert_eq!((bed.actual, bed.power), (60.0, Some(127)));
+        assert_eq!(bed.power_percent(), Some(100.0));
+        let hotend = &report.heaters[&report::Heater::Hotend(0)];
+        assert!((hotend.power_percent().unwrap() - 87.0 * 100.0 / 255.0).abs() < 0.5);
+        assert!(dialect.position_in_ok());
+        let position = report::PositionReport::parse("C: X:10.0000 Y:20.0000 Z:0.3000 E:0.0000");
+        assert_eq!(position.map(|p| [p.x, p.y, p.z]), Some([10.0, 20.0, 0.3]));
+    }
+
+    #[test]
+    fn tools_and_emergency_stop() {
+        assert_eq!(
+            for_flavour(Flavour::Klipper).select_tool(1).code(),
+            "ACTIVATE_EXTRUDER EXTRUDER=extruder1"
+        );
+        assert_eq!(for_flavour(Flavour::Marlin).select_tool(1).code(), "T1");
+
+        let mut capabilities = firmware::Capabilities::default();
+        let marlin = for_flavour(Flavour::Auto);
+        assert!(!marlin.immediate_emergency_stop(&capabilities));
+        capabilities.parse_line("Cap:EMERGENCY_PARSER:1");
+        assert!(marlin.immediate_emergency_stop(&capabilities));
+        assert_eq!(marlin.queries(), vec![gcode::Line::command('M', 503)]);
+        assert!(for_flavour(Flavour::Klipper).queries().is_empty());
+    }
+}
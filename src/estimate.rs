@@ -0,0 +1,484 @@
+use crate::gcode;
+use crate::machine;
+use crate::toolpath;
+
+/// Motion limits of the printer, as reported by `M503` or set in the G-code file
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineLimits {
+    /// Maximum feedrate of X, Y, Z and E in mm/s (`M203`)
+    pub max_feedrate: [f32; 4],
+    /// Maximum acceleration of X, Y, Z and E in mm/s² (`M201`)
+    pub max_acceleration: [f32; 4],
+    /// Acceleration of printing moves in mm/s² (`M204 P`)
+    pub acceleration: f32,
+    /// Acceleration of extruder only moves in mm/s² (`M204 R`)
+    pub retract_acceleration: f32,
+    /// Acceleration of travel moves in mm/s² (`M204 T`)
+    pub travel_acceleration: f32,
+    /// Jerk of X, Y, Z and E in mm/s (`M205`)
+    pub jerk: [f32; 4],
+    /// Junction deviation in mm, used instead of the jerk if set (`M205 J`)
+    pub junction_deviation: Option<f32>,
+}
+
+impl Default for MachineLimits {
+    // The defaults of the Marlin example configuration
+    fn default() -> Self {
+        MachineLimits {
+            max_feedrate: [300.0, 300.0, 5.0, 25.0],
+            max_acceleration: [3000.0, 3000.0, 100.0, 10000.0],
+            acceleration: 3000.0,
+            retract_acceleration: 3000.0,
+            travel_acceleration: 3000.0,
+            jerk: [10.0, 10.0, 0.3, 5.0],
+            junction_deviation: None,
+        }
+    }
+}
+
+impl MachineLimits {
+    /// Take over the limits of `M201`, `M203`, `M204` and `M205`, returns false for any other line
+    pub fn apply(&mut self, line: &gcode::Line) -> bool {
+        let number = match line.command {
+            Some(command) if command.letter == 'M' => command.number,
+            _ => return false,
+        };
+        match number {
+            201 => set_axes(&mut self.max_acceleration, line),
+            203 => set_axes(&mut self.max_feedrate, line),
+            204 => {
+                // Older firmware sets printing and travel acceleration at once
+                if let Some(value) = line.get('S') {
+                    self.acceleration = value as f32;
+                    self.travel_acceleration = value as f32;
+                }
+                if let Some(value) = line.get('P') {
+                    self.acceleration = value as f32;
+                }
+                if let Some(value) = line.get('R') {
+                    self.retract_acceleration = value as f32;
+                }
+                if let Some(value) = line.get('T') {
+                    self.travel_acceleration = value as f32;
+                }
+            }
+            205 => {
+                set_axes(&mut self.jerk, line);
+                if let Some(value) = line.get('J') {
+                    self.junction_deviation = if value > 0.0 {
+                        Some(value as f32)
+                    } else {
+                        None
+                    };
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+fn set_axes(values: &mut [f32; 4], line: &gcode::Line) {
+    for (value, name) in values.iter_mut().zip(['X', 'Y', 'Z', 'E'].iter()) {
+        if let Some(new_value) = line.get(*name) {
+            *value = new_value as f32;
+        }
+    }
+}
+
+/// The filament, needed to calculate the weight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filament {
+    /// Diameter in mm
+    pub diameter: f32,
+    /// Density in g/cm³
+    pub density: f32,
+}
+
+impl Default for Filament {
+    // 1.75 mm PLA
+    fn default() -> Self {
+        Filament {
+            diameter: 1.75,
+            density: 1.24,
+        }
+    }
+}
+
+/// Result of running a file through the planner
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+    /// Estimated print time in seconds at the end of every line
+    pub line_times: Vec<f32>,
+    /// Estimated print time in seconds of every layer of the toolpath
+    pub layer_times: Vec<f32>,
+    /// Filament used in mm
+    pub filament_length: f32,
+    /// Filament used in g
+    pub filament_weight: f32,
+}
+
+/// A move as seen by the planner
+struct Move {
+    line: usize,
+    /// Length in mm, of the extruder for extruder only moves
+    distance: f32,
+    /// Share of every axis in the distance
+    unit: [f32; 4],
+    /// Speed in mm/s the move should be done at
+    nominal_speed: f32,
+    /// Acceleration in mm/s²
+    acceleration: f32,
+    /// Highest speed at the start of the move the junction to the previous move allows
+    max_entry_speed: f32,
+}
+
+/// Run the lines through a simplified trapezoidal motion planner like the firmware uses.
+/// The layers are taken from the toolpath of the same lines.
+pub fn estimate(
+    lines: &[gcode::Line],
+    layers: &[toolpath::Layer],
+    limits: &MachineLimits,
+    filament: &Filament,
+) -> Estimate {
+    // The file may change the limits
+    let mut limits = limits.clone();
+    let mut machine = machine::MachineState::default();
+    let mut times = vec![0.0; lines.len()];
+    let mut moves: Vec<Move> = Vec::new();
+    let mut filament_length = 0.0;
+
+    for (index, line) in lines.iter().enumerate() {
+        if limits.apply(line) {
+            continue;
+        }
+        // The firmware waits for all moves to finish before these commands
+        if line.is('G', 4)
+            || line.is('G', 28)
+            || line.is('G', 29)
+            || line.is('M', 109)
+            || line.is('M', 190)
+            || line.is('M', 191)
+            || line.is('M', 400)
+        {
+            plan(&moves, &mut times);
+            moves.clear();
+        }
+        // Dwell for P milliseconds or S seconds
+        if line.is('G', 4) {
+            times[index] += line.get('P').map_or(0.0, |p| p as f32 / 1000.0)
+                + line.get('S').map_or(0.0, |s| s as f32);
+        }
+
+        let before = machine.position;
+        machine.apply(line);
+        if !machine::is_move(line) {
+            continue;
+        }
+        let after = machine.position;
+        filament_length += after[3] - before[3];
+
+        // Arcs are planned as the short straight moves the firmware splits them into
+        let mut from = before;
+        for to in machine::arc_points(line, &before, &after) {
+            if let Some(mut next) = straight_move(&limits, machine.feedrate, index, &from, &to) {
+                next.max_entry_speed = match moves.last() {
+                    Some(previous) => junction_speed(&limits, previous, &next),
+                    None => start_speed(&limits, &next),
+                };
+                moves.push(next);
+            }
+            from = to;
+        }
+    }
+    plan(&moves, &mut times);
+
+    let layer_times = layers
+        .iter()
+        .enumerate()
+        .map(|(index, layer)| {
+            let end = layers
+                .get(index + 1)
+                .map_or(lines.len(), |next| next.first_line);
+            times[layer.first_line..end].iter().sum()
+        })
+        .collect();
+
+    let mut total = 0.0;
+    let line_times = times
+        .iter()
+        .map(|time| {
+            total += time;
+            total
+        })
+        .collect();
+
+    let filament_length: f32 = filament_length.max(0.0);
+    let radius = filament.diameter / 2.0;
+    // mm³ to cm³
+    let filament_volume = filament_length * std::f32::consts::PI * radius * radius / 1000.0;
+
+    Estimate {
+        line_times,
+        layer_times,
+        filament_length,
+        filament_weight: filament_volume * filament.density,
+    }
+}
+
+/// A move from one position to another, `None` if it goes nowhere
+fn straight_move(
+    limits: &MachineLimits,
+    feedrate: f32,
+    line: usize,
+    from: &[f32; 4],
+    to: &[f32; 4],
+) -> Option<Move> {
+    let mut delta = [0.0; 4];
+    for axis in 0..4 {
+        delta[axis] = to[axis] - from[axis];
+    }
+    let xyz_distance = (delta[0].powi(2) + delta[1].powi(2) + delta[2].powi(2)).sqrt();
+    let distance = if xyz_distance > 0.0 {
+        xyz_distance
+    } else {
+        delta[3].abs()
+    };
+    if distance <= 0.0 {
+        return None;
+    }
+
+    let mut unit = [0.0; 4];
+    let mut nominal_speed = feedrate / 60.0;
+    let mut acceleration = if xyz_distance <= 0.0 {
+        limits.retract_acceleration
+    } else if delta[3] != 0.0 {
+        limits.acceleration
+    } else {
+        limits.travel_acceleration
+    };
+    // Every axis has to stay within its own limits
+    for axis in 0..4 {
+        unit[axis] = delta[axis] / distance;
+        if unit[axis] != 0.0 {
+            nominal_speed = nominal_speed.min(limits.max_feedrate[axis] / unit[axis].abs());
+            acceleration = acceleration.min(limits.max_acceleration[axis] / unit[axis].abs());
+        }
+    }
+    if nominal_speed <= 0.0 {
+        return None;
+    }
+
+    Some(Move {
+        line,
+        distance,
+        unit,
+        nominal_speed,
+        acceleration,
+        max_entry_speed: 0.0,
+    })
+}
+
+/// Speed a move can start with from standstill
+fn start_speed(limits: &MachineLimits, next: &Move) -> f32 {
+    if limits.junction_deviation.is_some() {
+        return 0.0;
+    }
+    let mut speed = next.nominal_speed;
+    for axis in 0..4 {
+        if next.unit[axis] != 0.0 {
+            speed = speed.min(limits.jerk[axis] / next.unit[axis].abs());
+        }
+    }
+    speed
+}
+
+/// Highest speed at the junction of two moves
+fn junction_speed(limits: &MachineLimits, previous: &Move, next: &Move) -> f32 {
+    let max_speed = previous.nominal_speed.min(next.nominal_speed);
+    match limits.junction_deviation {
+        // The junction is passed on a circle that deviates this much from the corner
+        Some(deviation) => {
+            let cos_theta: f32 = -previous
+                .unit
+                .iter()
+                .zip(next.unit.iter())
+                .map(|(a, b)| a * b)
+                .sum::<f32>();
+            if cos_theta > 0.999_999 {
+                // Reversal
+                0.0
+            } else if cos_theta < -0.999_999 {
+                // Straight line
+                max_speed
+            } else {
+                let sin_theta_d2 = (0.5 * (1.0 - cos_theta)).sqrt();
+                (next.acceleration * deviation * sin_theta_d2 / (1.0 - sin_theta_d2))
+                    .sqrt()
+                    .min(max_speed)
+            }
+        }
+        // The speed change of every axis is limited by its jerk
+        None => {
+            let mut factor: f32 = 1.0;
+            for axis in 0..4 {
+                let change = (next.unit[axis] - previous.unit[axis]).abs() * max_speed;
+                if change > limits.jerk[axis] {
+                    factor = factor.min(limits.jerk[axis] / change);
+                }
+            }
+            max_speed * factor
+        }
+    }
+}
+
+/// Plan a sequence of moves that starts and ends at standstill and add their times
+fn plan(moves: &[Move], times: &mut [f32]) {
+    let mut speeds: Vec<f32> = moves.iter().map(|next| next.max_entry_speed).collect();
+    // The exit speed of the last move
+    speeds.push(0.0);
+
+    // Every move must be able to slow down to the entry speed of the next move
+    for (index, next) in moves.iter().enumerate().rev() {
+        let reachable =
+            (speeds[index + 1].powi(2) + 2.0 * next.acceleration * next.distance).sqrt();
+        speeds[index] = speeds[index].min(reachable);
+    }
+    // and to speed up from its own entry speed
+    for (index, next) in moves.iter().enumerate() {
+        let reachable = (speeds[index].powi(2) + 2.0 * next.acceleration * next.distance).sqrt();
+        speeds[index + 1] = speeds[index + 1].min(reachable);
+    }
+
+    for (index, next) in moves.iter().enumerate() {
+        times[next.line] += trapezoid_time(next, speeds[index], speeds[index + 1]);
+    }
+}
+
+/// Time of a move that accelerates, cruises and decelerates
+fn trapezoid_time(next: &Move, entry_speed: f32, exit_speed: f32) -> f32 {
+    let speed = next.nominal_speed;
+    let acceleration = next.acceleration;
+    if acceleration <= 0.0 {
+        return next.distance / speed;
+    }
+    let accelerate_distance = (speed.powi(2) - entry_speed.powi(2)) / (2.0 * acceleration);
+    let decelerate_distance = (speed.powi(2) - exit_speed.powi(2)) / (2.0 * acceleration);
+    if accelerate_distance + decelerate_distance <= next.distance {
+        (speed - entry_speed) / acceleration
+            + (speed - exit_speed) / acceleration
+            + (next.distance - accelerate_distance - decelerate_distance) / speed
+    } else {
+        // The nominal speed is never reached
+        let peak_speed =
+            ((2.0 * acceleration * next.distance + entry_speed.powi(2) + exit_speed.powi(2)) / 2.0)
+                .sqrt()
+                .max(entry_speed)
+                .max(exit_speed);
+        (peak_speed - entry_speed) / acceleration + (peak_speed - exit_speed) / acceleration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Vec<gcode::Line> {
+        lines
+            .iter()
+            .map(|line| gcode::Line::parse(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn single_move() {
+        let limits = MachineLimits {
+            jerk: [0.0; 4],
+            ..MachineLimits::default()
+        };
+        // 100 mm at 50 mm/s with 1000 mm/s²: 1.25 mm to accelerate and to decelerate
+        let lines = parse(&["M204 T1000", "G1 X100 F3000"]);
+        let result = estimate(&lines, &[], &limits, &Filament::default());
+        assert!((result.line_times[1] - 2.05).abs() < 1e-4);
+
+        // Too short to reach the feedrate
+        let lines = parse(&["M204 T1000", "G1 X1 F3000"]);
+        let result = estimate(&lines, &[], &limits, &Filament::default());
+        assert!((result.line_times[1] - 2.0 * (0.001f32).sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn junctions() {
+        let limits = MachineLimits::default();
+        let filament = Filament::default();
+        let single = estimate(&parse(&["G1 X100 F3000"]), &[], &limits, &filament);
+        // No slow down between moves in a straight line
+        let straight = estimate(
+            &parse(&["G1 X50 F3000", "G1 X100"]),
+            &[],
+            &limits,
+            &filament,
+        );
+        assert!((straight.line_times[1] - single.line_times[0]).abs() < 1e-4);
+        // but at corners and when the planner is emptied
+        let corner = estimate(
+            &parse(&["G1 X50 F3000", "G1 X50 Y50"]),
+            &[],
+            &limits,
+            &filament,
+        );
+        assert!(corner.line_times[1] > single.line_times[0]);
+        let stop = estimate(
+            &parse(&["G1 X50 F3000", "M400", "G1 X100"]),
+            &[],
+            &limits,
+            &filament,
+        );
+        assert!(stop.line_times[2] > single.line_times[0]);
+    }
+
+    #[test]
+    fn arcs() {
+        let limits = MachineLimits::default();
+        let filament = Filament::default();
+        // Half a circle with a radius of 10 mm at 50 mm/s
+        let arc = estimate(
+            &parse(&["G1 X10 F3000", "G2 X10 Y20 I0 J10 E2"]),
+            &[],
+            &limits,
+            &filament,
+        );
+        let time = arc.line_times[1] - arc.line_times[0];
+        let length = std::f32::consts::PI * 10.0;
+        assert!(time > length / 50.0 && time < length / 50.0 + 0.5);
+        assert!((arc.filament_length - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn layers_and_filament() {
+        let lines = parse(&[
+            "M204 P1000 T1000",
+            "G1 Z0.2 F600",
+            "G1 X10 Y10 E1 F1200",
+            "G1 E-1 F2400",
+            "G1 Z0.4",
+            "G1 E1",
+            "G1 X20 E2",
+            "G4 S2",
+            "G1 X0 Y0 E3",
+        ]);
+        let toolpath = toolpath::Toolpath::new(&lines);
+        let result = estimate(
+            &lines,
+            &toolpath.layers,
+            &MachineLimits::default(),
+            &Filament::default(),
+        );
+        assert_eq!(result.layer_times.len(), 2);
+        assert!((result.filament_length - 3.0).abs() < 1e-4);
+        assert!(result.layer_times[1] > 2.0);
+        let total: f32 = result.layer_times.iter().sum();
+        // The moves before the first layer are not part of any layer
+        assert!((total + result.line_times[1] - result.line_times.last().unwrap()).abs() < 1e-3);
+    }
+}
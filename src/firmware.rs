@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+
+/// The firmware sends temperature reports on its own after `M155 S<seconds>`
+pub const AUTOREPORT_TEMP: &str = "AUTOREPORT_TEMP";
+/// The firmware sends position reports on its own after `M154 S<seconds>`
+pub const AUTOREPORT_POS: &str = "AUTOREPORT_POS";
+/// `M112` is handled as soon as it arrives, even while a command blocks
+pub const EMERGENCY_PARSER: &str = "EMERGENCY_PARSER";
+
+/// What the firmware tells about itself in answer to `M115`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Capabilities {
+    pub firmware_name: Option<String>,
+    pub firmware_version: Option<String>,
+    pub machine_type: Option<String>,
+    pub extruder_count: Option<u32>,
+    pub uuid: Option<String>,
+    /// The `Cap:` lines, e.g. `Cap:EEPROM:1`
+    pub capabilities: BTreeMap<String, bool>,
+}
+
+impl Capabilities {
+    /// Take a line of the answer to `M115`, returns false if it is not part of it
+    pub fn parse_line(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if let Some(capability) = line.strip_prefix("Cap:") {
+            let mut parts = capability.rsplitn(2, ':');
+            let value = parts.next().unwrap_or("").trim();
+            return match parts.next() {
+                Some(name) => {
+                    self.capabilities
+                        .insert(name.trim().to_string(), value == "1");
+                    true
+                }
+                None => false,
+            };
+        }
+
+        if !line.contains("FIRMWARE_NAME:") {
+            return false;
+        }
+        for (key, value) in fields(line) {
+            match key {
+                "FIRMWARE_NAME" => self.firmware_name = Some(value),
+                "FIRMWARE_VERSION" => self.firmware_version = Some(value),
+                "MACHINE_TYPE" => self.machine_type = Some(value),
+                "EXTRUDER_COUNT" => self.extruder_count = value.parse().ok(),
+                "UUID" => self.uuid = Some(value),
+                _ => (),
+            }
+        }
+        true
+    }
+
+    /// True if the firmware reported the capability as enabled
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.get(capability).copied().unwrap_or(false)
+    }
+}
+
+/// The `KEY:value` pairs of a line, values may contain spaces like `MACHINE_TYPE:Original Prusa i3 MK3S`
+fn fields(line: &str) -> Vec<(&str, String)> {
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for word in line.split_whitespace() {
+        let key = word.find(':').map(|pos| (&word[..pos], &word[pos + 1..]));
+        match key {
+            Some((key, value))
+                if !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c == '_' || c == '-') =>
+            {
+                let value = if value.is_empty() {
+                    vec![]
+                } else {
+                    vec![value]
+                };
+                fields.push((key, value));
+            }
+            // Words before the first key like the `ok` of Klipper are left out
+            _ => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(word);
+                }
+            }
+        }
+    }
+    fields
+        .into_iter()
+        // Smoothieware separates the fields with commas
+        .map(|(key, value)| (key, value.join(" ").trim_end_matches(',').to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marlin() {
+        let mut capabilities = Capabilities::default();
+        assert!(capabilities.parse_line(
+            "FIRMWARE_NAME:Marlin 2.0.9.3 (Sep 26 2022 12:00:00) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin \
+             PROTOCOL_VERSION:1.0 MACHINE_TYPE:Ender-3 Pro EXTRUDER_COUNT:1 UUID:cede2a2f-41a2-4748-9b12-c55c62f367ff"
+        ));
+        assert!(capabilities.parse_line("Cap:AUTOREPORT_TEMP:1"));
+        assert!(capabilities.parse_line("Cap:SDCARD:0"));
+        assert!(!capabilities.parse_line("ok"));
+        assert!(!capabilities.parse_line("echo:busy: processing"));
+
+        assert_eq!(
+            capabilities.firmware_name.as_deref(),
+            Some("Marlin 2.0.9.3 (Sep 26 2022 12:00:00)")
+        );
+        assert_eq!(capabilities.machine_type.as_deref(), Some("Ender-3 Pro"));
+        assert_eq!(capabilities.extruder_count, Some(1));
+        assert_eq!(
+            capabilities.uuid.as_deref(),
+            Some("cede2a2f-41a2-4748-9b12-c55c62f367ff")
+        );
+        assert!(capabilities.has(AUTOREPORT_TEMP));
+        assert!(!capabilities.has("SDCARD"));
+        assert!(!capabilities.has(EMERGENCY_PARSER));
+    }
+
+    #[test]
+    fn other_firmwares() {
+        let mut capabilities = Capabilities::default();
+        capabilities.parse_line(
+            "FIRMWARE_NAME: RepRapFirmware for Duet 3 MB6HC FIRMWARE_VERSION: 3.4.5 \
+             ELECTRONICS: Duet 3 MB6HC v1.01 FIRMWARE_DATE: 2022-11-30",
+        );
+        assert_eq!(
+            capabilities.firmware_name.as_deref(),
+            Some("RepRapFirmware for Duet 3 MB6HC")
+        );
+        assert_eq!(capabilities.firmware_version.as_deref(), Some("3.4.5"));
+
+        let mut capabilities = Capabilities::default();
+        capabilities.parse_line("ok FIRMWARE_VERSION:v0.11.0-90 FIRMWARE_NAME:Klipper");
+        assert_eq!(capabilities.firmware_name.as_deref(), Some("Klipper"));
+        assert_eq!(capabilities.firmware_version.as_deref(), Some("v0.11.0-90"));
+
+        let mut capabilities = Capabilities::default();
+        capabilities.parse_line(
+            "FIRMWARE_NAME:Smoothieware, FIRMWARE_URL:http%3A//smoothieware.org, \
+             X-FIRMWARE_VERSION:edge-94de12c, X-AXES:5",
+        );
+        assert_eq!(capabilities.firmware_name.as_deref(), Some("Smoothieware"));
+    }
+}
@@ -0,0 +1,473 @@
+use std::fmt;
+
+/// Commands that take the rest of the line as a string argument
+const TEXT_COMMANDS: [(char, u32); 7] = [
+    ('M', 23),
+    ('M', 28),
+    ('M', 30),
+    ('M', 32),
+    ('M', 117),
+    ('M', 118),
+    ('M', 928),
+];
+
+/// A command word like `G1`, `M104`, `T0` or `G29.1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub letter: char,
+    pub number: u32,
+    pub subcode: Option<u32>,
+}
+
+/// A parameter like `X10.5`, flags like the `X` in `G28 X` have no value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub letter: char,
+    pub value: Option<f64>,
+    /// A quoted string instead of a number, like the `P "MK3S"` of PrusaSlicer's `M862.3`
+    pub string: Option<String>,
+}
+
+/// A single line of G-code
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Line {
+    pub line_number: Option<u32>,
+    pub command: Option<Command>,
+    pub parameters: Vec<Parameter>,
+    /// String argument of commands like `M117` and `M23`, or a whole extended command like Klipper's `PRINT_START`
+    pub text: Option<String>,
+    pub comment: Option<String>,
+    pub checksum: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    InvalidNumber(String),
+    UnexpectedCharacter(char),
+    InvalidChecksum(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidNumber(number) => write!(f, "invalid number \"{}\"", number),
+            ParseError::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            ParseError::InvalidChecksum(checksum) => {
+                write!(f, "invalid checksum \"{}\"", checksum)
+            }
+        }
+    }
+}
+
+impl Line {
+    /// A line with just a command like `G1` or `M105`
+    pub fn command(letter: char, number: u32) -> Line {
+        Line {
+            command: Some(Command {
+                letter,
+                number,
+                subcode: None,
+            }),
+            ..Line::default()
+        }
+    }
+
+    /// Add a parameter with a value
+    pub fn with(mut self, letter: char, value: f64) -> Line {
+        self.parameters.push(Parameter {
+            letter,
+            value: Some(value),
+            string: None,
+        });
+        self
+    }
+
+    /// A line that is sent as it is, e.g. one the parser does not understand
+    pub fn raw(line: &str) -> Line {
+        let (code, comment) = match line.find(';') {
+            Some(pos) => (&line[..pos], Some(line[pos + 1..].trim().to_string())),
+            None => (line, None),
+        };
+        let code = code.trim();
+        Line {
+            text: if code.is_empty() {
+                None
+            } else {
+                Some(code.to_string())
+            },
+            comment,
+            ..Line::default()
+        }
+    }
+
+    /// Parse a line of G-code
+    pub fn parse(line: &str) -> Result<Line, ParseError> {
+        let mut result = Line::default();
+
+        // Everything behind a semicolon is a comment
+        let mut code = match line.find(';') {
+            Some(pos) => {
+                result.comment = Some(line[pos + 1..].trim().to_string());
+                &line[..pos]
+            }
+            None => line,
+        };
+
+        // A checksum can only be at the end of a line with a line number,
+        // otherwise a star is part of the text like in `M117 ***`
+        let numbered = code
+            .trim_start()
+            .strip_prefix(|c| c == 'N' || c == 'n')
+            .is_some_and(|rest| rest.trim_start().starts_with(|c: char| c.is_ascii_digit()));
+        if let Some(pos) = code.rfind('*').filter(|_| numbered) {
+            let checksum = code[pos + 1..].trim();
+            result.checksum = Some(
+                checksum
+                    .parse()
+                    .map_err(|_| ParseError::InvalidChecksum(checksum.to_string()))?,
+            );
+            code = &code[..pos];
+        }
+
+        let mut rest = code.trim();
+
+        // Extended commands like `SET_HEATER_TEMPERATURE HEATER=extruder TARGET=200`,
+        // possibly behind a line number
+        let mut words = rest.splitn(2, char::is_whitespace);
+        let first_word = words.next().unwrap_or("");
+        let (line_number, extended) = match first_word
+            .strip_prefix(|c| c == 'N' || c == 'n')
+            .and_then(|number| number.parse::<u32>().ok())
+        {
+            Some(line_number) => (Some(line_number), words.next().unwrap_or("").trim()),
+            None => (None, rest),
+        };
+        let first_word = extended.split_whitespace().next().unwrap_or("");
+        if first_word.len() > 1
+            && first_word
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c == '_' || c.is_ascii_digit())
+            && first_word
+                .chars()
+                .nth(1)
+                .is_some_and(|c| !is_number_char(c))
+        {
+            result.line_number = line_number;
+            result.text = Some(extended.to_string());
+            return Ok(result);
+        }
+
+        while let Some(c) = rest.chars().next() {
+            if c.is_whitespace() {
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            // Comments in parentheses
+            if c == '(' {
+                let (comment, remaining) = match rest.find(')') {
+                    Some(end) => (rest[1..end].trim(), &rest[end + 1..]),
+                    None => (rest[1..].trim(), ""),
+                };
+                result.comment = Some(match result.comment.take() {
+                    Some(previous) => format!("{} {}", comment, previous),
+                    None => comment.to_string(),
+                });
+                rest = remaining;
+                continue;
+            }
+            if !c.is_ascii_alphabetic() {
+                return Err(ParseError::UnexpectedCharacter(c));
+            }
+
+            let letter = c.to_ascii_uppercase();
+            rest = &rest[1..];
+
+            if rest.trim_start().starts_with('"') {
+                let (string, remaining) = parse_string(rest.trim_start());
+                result.parameters.push(Parameter {
+                    letter,
+                    value: None,
+                    string: Some(string),
+                });
+                rest = remaining;
+                continue;
+            }
+
+            let number_len = rest
+                .char_indices()
+                .find(|&(pos, c)| !(is_number_char(c) || (c == ' ' && pos == 0)))
+                .map_or(rest.len(), |(pos, _)| pos);
+            let number = rest[..number_len].trim();
+            rest = &rest[number_len..];
+
+            if result.command.is_none()
+                && result.parameters.is_empty()
+                && letter == 'N'
+                && result.line_number.is_none()
+            {
+                result.line_number = Some(
+                    number
+                        .parse()
+                        .map_err(|_| ParseError::InvalidNumber(number.to_string()))?,
+                );
+            } else if result.command.is_none()
+                && result.parameters.is_empty()
+                && (letter == 'G' || letter == 'M' || letter == 'T')
+            {
+                let (number, subcode) = match number.find('.') {
+                    Some(pos) => (&number[..pos], Some(&number[pos + 1..])),
+                    None => (number, None),
+                };
+                let invalid = || ParseError::InvalidNumber(number.to_string());
+                let command = Command {
+                    letter,
+                    number: number.parse().map_err(|_| invalid())?,
+                    subcode: match subcode {
+                        Some(subcode) => Some(subcode.parse().map_err(|_| invalid())?),
+                        None => None,
+                    },
+                };
+                result.command = Some(command);
+
+                if TEXT_COMMANDS.contains(&(letter, command.number)) {
+                    // Only a single space separates the command from the text
+                    let text = rest.strip_prefix(' ').unwrap_or(rest);
+                    result.text = Some(text.trim_end().to_string());
+                    rest = "";
+                }
+            } else {
+                let value = if number.is_empty() {
+                    None
+                } else {
+                    Some(
+                        number
+                            .parse()
+                            .map_err(|_| ParseError::InvalidNumber(number.to_string()))?,
+                    )
+                };
+                result.parameters.push(Parameter {
+                    letter,
+                    value,
+                    string: None,
+                });
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// True if the command matches, e.g. `line.is('G', 1)`
+    pub fn is(&self, letter: char, number: u32) -> bool {
+        match self.command {
+            Some(command) => command.letter == letter && command.number == number,
+            None => false,
+        }
+    }
+
+    /// Value of a parameter
+    pub fn get(&self, letter: char) -> Option<f64> {
+        self.parameters
+            .iter()
+            .find(|parameter| parameter.letter == letter)
+            .and_then(|parameter| parameter.value)
+    }
+
+    /// True if the parameter is given, with or without a value
+    pub fn has(&self, letter: char) -> bool {
+        self.parameters
+            .iter()
+            .any(|parameter| parameter.letter == letter)
+    }
+
+    /// True if there is nothing to send, e.g. a comment only line
+    pub fn is_empty(&self) -> bool {
+        self.command.is_none() && self.parameters.is_empty() && self.text.is_none()
+    }
+
+    /// The code as sent to the printer, without line number, checksum and comment
+    pub fn code(&self) -> String {
+        let mut words = Vec::new();
+        if let Some(command) = self.command {
+            words.push(match command.subcode {
+                Some(subcode) => format!("{}{}.{}", command.letter, command.number, subcode),
+                None => format!("{}{}", command.letter, command.number),
+            });
+        }
+        for parameter in self.parameters.iter() {
+            words.push(match (parameter.value, &parameter.string) {
+                (_, Some(string)) => {
+                    format!("{} \"{}\"", parameter.letter, string.replace('"', "\"\""))
+                }
+                (Some(value), None) => format!("{}{}", parameter.letter, format_number(value)),
+                (None, None) => parameter.letter.to_string(),
+            });
+        }
+        if let Some(ref text) = self.text {
+            words.push(text.clone());
+        }
+        words.join(" ")
+    }
+
+    /// The code wrapped as `N<line> <code>*<checksum>`
+    pub fn framed(&self, line_number: u32) -> String {
+        let line = format!("N{} {}", line_number, self.code());
+        format!("{}*{}", line, checksum(&line))
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut line = match self.line_number {
+            Some(line_number) => format!("N{} {}", line_number, self.code()),
+            None => self.code(),
+        };
+        if let Some(checksum) = self.checksum {
+            line = format!("{}*{}", line, checksum);
+        }
+        if let Some(ref comment) = self.comment {
+            if line.is_empty() {
+                line = format!("; {}", comment);
+            } else {
+                line = format!("{} ; {}", line, comment);
+            }
+        }
+        write!(f, "{}", line)
+    }
+}
+
+/// The checksum used by the firmware: all bytes of the line xor'ed
+pub fn checksum(line: &str) -> u8 {
+    line.bytes().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+/// A string in double quotes at the start of the input and the input after it.
+/// Quotes inside are doubled as in `"say ""hi"""`, an unterminated string ends with the line.
+fn parse_string(input: &str) -> (String, &str) {
+    let mut string = String::new();
+    let mut chars = input.char_indices().skip(1).peekable();
+    while let Some((pos, c)) = chars.next() {
+        if c == '"' {
+            match chars.peek() {
+                Some(&(_, '"')) => {
+                    chars.next();
+                }
+                _ => return (string, &input[pos + 1..]),
+            }
+        }
+        string.push(c);
+    }
+    (string, "")
+}
+
+fn is_number_char(c: char) -> bool {
+    c.is_ascii_digit() || c == '.' || c == '-' || c == '+'
+}
+
+/// The shortest number that reads back as the same value, nothing is rounded away.
+/// Values that came from an `f32` are written as such, so the settings stay `0.3`
+/// instead of `0.30000001192092896`.
+fn format_number(value: f64) -> String {
+    let number = if f64::from(value as f32) == value {
+        format!("{}", value as f32)
+    } else {
+        format!("{}", value)
+    };
+    if number == "-0" {
+        "0".to_string()
+    } else {
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_words() {
+        let line = Line::parse("N12 G1X10 Y-2.5 E0.3 F1500*45 ; perimeter").unwrap();
+        assert_eq!(line.line_number, Some(12));
+        assert!(line.is('G', 1));
+        assert_eq!(line.get('X'), Some(10.0));
+        assert_eq!(line.get('Y'), Some(-2.5));
+        assert_eq!(line.get('F'), Some(1500.0));
+        assert_eq!(line.checksum, Some(45));
+        assert_eq!(line.comment, Some("perimeter".to_string()));
+        assert_eq!(line.code(), "G1 X10 Y-2.5 E0.3 F1500");
+
+        let line = Line::parse("G28 X Y").unwrap();
+        assert!(line.has('X') && line.has('Y') && !line.has('Z'));
+        assert_eq!(line.get('X'), None);
+
+        let line = Line::parse("G29.1").unwrap();
+        assert_eq!(line.command.unwrap().subcode, Some(1));
+        assert_eq!(line.code(), "G29.1");
+
+        assert!(Line::parse("; just a comment").unwrap().is_empty());
+        assert!(Line::parse("G1 X1.2.3").is_err());
+        assert!(Line::parse("G1 #").is_err());
+    }
+
+    #[test]
+    fn parse_text_arguments() {
+        let line = Line::parse("M117 Printing: 10% done").unwrap();
+        assert!(line.is('M', 117));
+        assert_eq!(line.text, Some("Printing: 10% done".to_string()));
+        assert_eq!(line.code(), "M117 Printing: 10% done");
+
+        let line = Line::parse("M23 benchy.gco").unwrap();
+        assert_eq!(line.text, Some("benchy.gco".to_string()));
+
+        let line = Line::parse("M117 Layer 2 ***").unwrap();
+        assert_eq!(line.checksum, None);
+        assert_eq!(line.text, Some("Layer 2 ***".to_string()));
+
+        let line = Line::parse("M862.3 P \"MK3S\" ; printer model check").unwrap();
+        assert_eq!(line.parameters[0].string, Some("MK3S".to_string()));
+        assert_eq!(line.code(), "M862.3 P \"MK3S\"");
+        let line = Line::parse("M98 P\"say \"\"hi\"\".g\" R1").unwrap();
+        assert_eq!(line.parameters[0].string, Some("say \"hi\".g".to_string()));
+        assert_eq!(line.get('R'), Some(1.0));
+
+        let line = Line::raw("G1 X1.2.3 ; broken");
+        assert_eq!(line.code(), "G1 X1.2.3");
+        assert_eq!(line.comment, Some("broken".to_string()));
+        assert!(Line::raw("; only a comment").is_empty());
+
+        let line = Line::parse("SET_HEATER_TEMPERATURE HEATER=extruder TARGET=200").unwrap();
+        assert_eq!(line.command, None);
+        assert_eq!(
+            line.code(),
+            "SET_HEATER_TEMPERATURE HEATER=extruder TARGET=200"
+        );
+        let framed = line.framed(5);
+        let line = Line::parse(&framed).unwrap();
+        assert_eq!(line.line_number, Some(5));
+        assert_eq!(
+            line.checksum,
+            Some(checksum(&framed[..framed.rfind('*').unwrap()]))
+        );
+        assert_eq!(
+            line.text,
+            Some("SET_HEATER_TEMPERATURE HEATER=extruder TARGET=200".to_string())
+        );
+    }
+
+    #[test]
+    fn write_lines() {
+        let line = Line::command('M', 110).with('N', 0.0);
+        assert_eq!(line.framed(0), "N0 M110 N0*125");
+
+        let line = Line::command('G', 1)
+            .with('X', f64::from(0.3f32))
+            .with('E', -2.0);
+        assert_eq!(line.code(), "G1 X0.3 E-2");
+        assert_eq!(Line::parse(&line.to_string()).unwrap().code(), line.code());
+
+        // Job lines are sent as written
+        for code in ["G92 E0.0000001", "G1 X0.123456789 Y-0.5 F1200"].iter() {
+            assert_eq!(Line::parse(code).unwrap().code(), *code);
+        }
+        assert_eq!(Line::parse("G1 E-0").unwrap().code(), "G1 E0");
+    }
+}
@@ -0,0 +1,625 @@
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::estimate;
+use crate::gcode;
+use crate::machine;
+use crate::report;
+use crate::toolpath;
+
+/// State of a print job
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Loaded,
+    Running,
+    Paused,
+    Cancelled,
+    Finished,
+}
+
+/// What happens when a job is paused or cancelled
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    /// Retract and park the head while paused
+    pub park_on_pause: bool,
+    /// Filament retracted on pause in mm
+    pub retract_length: f32,
+    /// Distance the nozzle is lifted on pause in mm
+    pub lift: f32,
+    /// X and Y the head is parked at
+    pub park_position: (f32, f32),
+    /// Feedrate for parking in mm/min
+    pub travel_feedrate: f32,
+    /// Feedrate for retracting in mm/min
+    pub retract_feedrate: f32,
+    /// Sent before the first line of a job
+    pub start_script: Vec<String>,
+    /// Sent after the last line of a job
+    pub end_script: Vec<String>,
+    /// Sent after a job was cancelled, before the heaters are turned off
+    pub cancel_script: Vec<String>,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        JobOptions {
+            park_on_pause: true,
+            retract_length: 2.0,
+            lift: 5.0,
+            park_position: (0.0, 0.0),
+            travel_feedrate: 3000.0,
+            retract_feedrate: 2100.0,
+            start_script: Vec::new(),
+            end_script: Vec::new(),
+            cancel_script: vec![
+                "G91".to_string(),
+                "G1 Z10 F600".to_string(),
+                "G90".to_string(),
+                "M84".to_string(),
+            ],
+        }
+    }
+}
+
+/// Everything needed to continue a paused job where it stopped
+struct PauseState {
+    machine: machine::MachineState,
+    temperatures: Option<report::TemperatureReport>,
+    parked: bool,
+}
+
+/// A G-code file that is streamed to the printer line by line
+pub struct PrintJob {
+    name: String,
+    lines: Vec<gcode::Line>,
+    estimate: estimate::Estimate,
+    toolpath: Rc<toolpath::Toolpath>,
+    /// Index of the next line handed to the command queue
+    next_line: usize,
+    /// Number of lines from the start the printer acknowledged
+    acknowledged_lines: usize,
+    bytes_total: usize,
+    bytes_sent: usize,
+    state: JobState,
+    /// The machine state after the last line handed to the command queue
+    machine: machine::MachineState,
+    pause_state: Option<PauseState>,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    paused_since: Option<Instant>,
+    /// Time spent paused, not counted as elapsed
+    paused_total: Duration,
+}
+
+/// Snapshot of a job shown in the Print tab
+#[derive(Debug, Clone)]
+pub struct JobStatus {
+    pub name: String,
+    pub state: JobState,
+    pub current_line: usize,
+    pub total_lines: usize,
+    pub bytes_sent: usize,
+    pub bytes_total: usize,
+    pub elapsed: Duration,
+    /// Estimated print time of the whole file
+    pub estimated_total: Duration,
+    pub progress: Progress,
+    /// Estimated time left, `None` before the job started
+    pub remaining: Option<Duration>,
+    /// Number of the layer being printed, 0 before the first layer
+    pub current_layer: usize,
+    pub total_layers: usize,
+    pub layer: Option<toolpath::Layer>,
+    /// Estimated print time of the current layer
+    pub layer_time: Duration,
+    /// Filament used in mm
+    pub filament_length: f32,
+    /// Filament used in g
+    pub filament_weight: f32,
+    pub bounding_box: Option<toolpath::BoundingBox>,
+}
+
+/// Progress of a job from 0.0 to 1.0
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Progress {
+    pub bytes: f32,
+    pub lines: f32,
+    /// Share of the estimated print time
+    pub time: f32,
+}
+
+impl PrintJob {
+    /// Load a G-code file, comments and blank lines are dropped.
+    /// Lines the parser does not understand are sent as they are.
+    pub fn load(
+        path: &Path,
+        limits: &estimate::MachineLimits,
+        filament: &estimate::Filament,
+    ) -> std::io::Result<PrintJob> {
+        let content = std::fs::read(path)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(PrintJob::parse(
+            name,
+            &String::from_utf8_lossy(&content),
+            limits,
+            filament,
+        ))
+    }
+
+    fn parse(
+        name: String,
+        text: &str,
+        limits: &estimate::MachineLimits,
+        filament: &estimate::Filament,
+    ) -> PrintJob {
+        let mut parsed = Vec::new();
+        for line in text.lines() {
+            parsed.push(gcode::Line::parse(line).unwrap_or_else(|_| gcode::Line::raw(line)));
+        }
+        // The toolpath needs the comments for the feature types
+        let toolpath = Rc::new(toolpath::Toolpath::new(&parsed));
+        let lines: Vec<gcode::Line> = parsed
+            .into_iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| gcode::Line {
+                comment: None,
+                ..line
+            })
+            .collect();
+        // Every line is sent with a trailing newline
+        let bytes_total = lines.iter().map(|line| line.code().len() + 1).sum();
+        let estimate = estimate::estimate(&lines, &toolpath.layers, limits, filament);
+
+        PrintJob {
+            name,
+            lines,
+            estimate,
+            toolpath,
+            next_line: 0,
+            acknowledged_lines: 0,
+            bytes_total,
+            bytes_sent: 0,
+            state: JobState::Loaded,
+            machine: machine::MachineState::default(),
+            pause_state: None,
+            started: None,
+            finished: None,
+            paused_since: None,
+            paused_total: Duration::from_secs(0),
+        }
+    }
+
+    /// Estimate again, e.g. after the limits were read from the printer
+    pub fn update_estimate(
+        &mut self,
+        limits: &estimate::MachineLimits,
+        filament: &estimate::Filament,
+    ) {
+        self.estimate = estimate::estimate(&self.lines, &self.toolpath.layers, limits, filament);
+    }
+
+    /// The lines sent to the printer, without comments
+    pub fn lines(&self) -> &[gcode::Line] {
+        &self.lines
+    }
+
+    pub fn toolpath(&self) -> Rc<toolpath::Toolpath> {
+        self.toolpath.clone()
+    }
+
+    /// Start streaming from the first line
+    pub fn start(&mut self) {
+        self.next_line = 0;
+        self.acknowledged_lines = 0;
+        self.bytes_sent = 0;
+        self.state = JobState::Running;
+        self.machine = machine::MachineState::default();
+        self.pause_state = None;
+        self.started = Some(Instant::now());
+        self.finished = None;
+        self.paused_since = None;
+        self.paused_total = Duration::from_secs(0);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == JobState::Running
+    }
+
+    /// Running or paused
+    pub fn is_active(&self) -> bool {
+        self.state == JobState::Running || self.state == JobState::Paused
+    }
+
+    /// Stop feeding lines and remember the state to resume from.
+    /// Returns the lines that retract and park the head.
+    pub fn pause(
+        &mut self,
+        options: &JobOptions,
+        temperatures: Option<report::TemperatureReport>,
+    ) -> Vec<gcode::Line> {
+        if !self.is_running() {
+            return Vec::new();
+        }
+        self.state = JobState::Paused;
+        self.paused_since = Some(Instant::now());
+        self.pause_state = Some(PauseState {
+            machine: self.machine,
+            temperatures,
+            parked: options.park_on_pause,
+        });
+
+        if !options.park_on_pause {
+            return Vec::new();
+        }
+        let travel_feedrate = f64::from(options.travel_feedrate);
+        vec![
+            gcode::Line::command('M', 83),
+            gcode::Line::command('G', 1)
+                .with('E', -f64::from(options.retract_length))
+                .with('F', f64::from(options.retract_feedrate)),
+            gcode::Line::command('G', 91),
+            gcode::Line::command('G', 1)
+                .with('Z', f64::from(options.lift))
+                .with('F', travel_feedrate),
+            gcode::Line::command('G', 90),
+            gcode::Line::command('G', 1)
+                .with('X', f64::from(options.park_position.0))
+                .with('Y', f64::from(options.park_position.1))
+                .with('F', travel_feedrate),
+        ]
+    }
+
+    /// Continue a paused job. Returns the lines that restore the state from before the pause.
+    pub fn resume(&mut self, options: &JobOptions) -> Vec<gcode::Line> {
+        if self.state != JobState::Paused {
+            return Vec::new();
+        }
+        self.state = JobState::Running;
+        if let Some(paused_since) = self.paused_since.take() {
+            self.paused_total += paused_since.elapsed();
+        }
+        let pause_state = match self.pause_state.take() {
+            Some(pause_state) => pause_state,
+            None => return Vec::new(),
+        };
+
+        let mut lines = Vec::new();
+        // Wait for the heaters in case they were changed while paused
+        if let Some(temperatures) = pause_state.temperatures {
+            for (heater, reading) in temperatures.heaters.iter() {
+                let target = match reading.target {
+                    Some(target) if target > 0.0 => f64::from(target),
+                    _ => continue,
+                };
+                lines.push(match heater {
+                    report::Heater::Hotend(index) => gcode::Line::command('M', 109)
+                        .with('T', *index as f64)
+                        .with('S', target),
+                    report::Heater::Bed => gcode::Line::command('M', 190).with('S', target),
+                    report::Heater::Chamber => gcode::Line::command('M', 191).with('S', target),
+                });
+            }
+        }
+
+        let machine = pause_state.machine;
+        let [x, y, z, e] = machine.position;
+        if pause_state.parked {
+            let travel_feedrate = f64::from(options.travel_feedrate);
+            lines.extend(vec![
+                gcode::Line::command('G', 90),
+                gcode::Line::command('G', 1)
+                    .with('X', f64::from(x))
+                    .with('Y', f64::from(y))
+                    .with('F', travel_feedrate),
+                gcode::Line::command('G', 1)
+                    .with('Z', f64::from(z))
+                    .with('F', travel_feedrate),
+                gcode::Line::command('M', 83),
+                gcode::Line::command('G', 1)
+                    .with('E', f64::from(options.retract_length))
+                    .with('F', f64::from(options.retract_feedrate)),
+            ]);
+        }
+        // Restore the extruder position and the positioning modes of the job
+        lines.push(gcode::Line::command('G', 92).with('E', f64::from(e)));
+        lines.push(gcode::Line::command(
+            'G',
+            if machine.absolute { 90 } else { 91 },
+        ));
+        lines.push(gcode::Line::command(
+            'M',
+            if machine.absolute_e { 82 } else { 83 },
+        ));
+        lines.push(gcode::Line::command('G', 1).with('F', f64::from(machine.feedrate)));
+        lines
+    }
+
+    /// Stop the job for good. Returns the cancel script followed by turning off all heaters.
+    pub fn cancel(
+        &mut self,
+        options: &JobOptions,
+        temperatures: Option<&report::TemperatureReport>,
+    ) -> Vec<gcode::Line> {
+        if !self.is_active() {
+            return Vec::new();
+        }
+        self.state = JobState::Cancelled;
+        self.finished = Some(Instant::now());
+        if let Some(paused_since) = self.paused_since.take() {
+            self.paused_total += paused_since.elapsed();
+        }
+        self.pause_state = None;
+
+        let mut lines = script(&options.cancel_script);
+        let heaters: Vec<report::Heater> = match temperatures {
+            Some(temperatures) => temperatures.heaters.keys().cloned().collect(),
+            None => vec![report::Heater::Hotend(0), report::Heater::Bed],
+        };
+        for heater in heaters {
+            lines.push(match heater {
+                report::Heater::Hotend(index) => gcode::Line::command('M', 104)
+                    .with('T', index as f64)
+                    .with('S', 0.0),
+                report::Heater::Bed => gcode::Line::command('M', 140).with('S', 0.0),
+                report::Heater::Chamber => gcode::Line::command('M', 141).with('S', 0.0),
+            });
+        }
+        lines.push(gcode::Line::command('M', 107));
+        lines
+    }
+
+    /// The next line for the command queue with its index
+    pub fn next_line(&mut self) -> Option<(usize, gcode::Line)> {
+        if !self.is_running() {
+            return None;
+        }
+        let line = self.lines.get(self.next_line)?;
+        let index = self.next_line;
+        self.next_line += 1;
+        self.bytes_sent += line.code().len() + 1;
+        self.machine.apply(line);
+        Some((index, line.clone()))
+    }
+
+    /// The printer acknowledged the line with the index
+    pub fn acknowledge(&mut self, index: usize) {
+        self.acknowledged_lines = self.acknowledged_lines.max(index + 1);
+    }
+
+    /// Finishes the job once the printer acknowledged the last line.
+    /// Returns true if the job just finished.
+    pub fn finish_if_done(&mut self) -> bool {
+        let total = self.lines.len();
+        if !self.is_running() || self.next_line < total || self.acknowledged_lines < total {
+            return false;
+        }
+        self.state = JobState::Finished;
+        self.finished = Some(Instant::now());
+        true
+    }
+
+    pub fn status(&self) -> JobStatus {
+        let end = self
+            .finished
+            .or(self.paused_since)
+            .unwrap_or_else(Instant::now);
+        let elapsed = match self.started {
+            Some(started) => (end - started).checked_sub(self.paused_total),
+            None => None,
+        }
+        .unwrap_or_default();
+
+        let fraction = |done: f32, total: f32| if total > 0.0 { done / total } else { 0.0 };
+        let line_times = &self.estimate.line_times;
+        let estimated_total = line_times.last().cloned().unwrap_or_default();
+        let estimated_done = match self.next_line {
+            0 => 0.0,
+            next_line => line_times[next_line - 1],
+        };
+        let progress = Progress {
+            bytes: fraction(self.bytes_sent as f32, self.bytes_total as f32),
+            lines: fraction(self.next_line as f32, self.lines.len() as f32),
+            time: fraction(estimated_done, estimated_total),
+        };
+
+        // The estimate is trusted at the beginning, the further the job gets the more
+        // the measured speed compared to the estimate counts
+        let remaining = match self.state {
+            JobState::Running | JobState::Paused => {
+                let estimated_remaining = estimated_total - estimated_done;
+                let measured_factor = if estimated_done > 0.0 {
+                    elapsed.as_secs_f32() / estimated_done
+                } else {
+                    1.0
+                };
+                let weight = progress.time.clamp(0.0, 1.0);
+                let factor = (1.0 - weight) + weight * measured_factor;
+                Some(Duration::from_secs_f32(
+                    (estimated_remaining * factor).max(0.0),
+                ))
+            }
+            JobState::Finished => Some(Duration::from_secs(0)),
+            JobState::Loaded | JobState::Cancelled => None,
+        };
+
+        let layers = &self.toolpath.layers;
+        let current_layer = layers
+            .iter()
+            .take_while(|layer| layer.first_line < self.next_line)
+            .count();
+        let layer = current_layer.checked_sub(1);
+
+        JobStatus {
+            name: self.name.clone(),
+            state: self.state,
+            current_line: self.next_line,
+            total_lines: self.lines.len(),
+            bytes_sent: self.bytes_sent,
+            bytes_total: self.bytes_total,
+            elapsed,
+            estimated_total: Duration::from_secs_f32(estimated_total.max(0.0)),
+            progress,
+            remaining,
+            current_layer,
+            total_layers: layers.len(),
+            layer: layer.map(|index| layers[index].clone()),
+            layer_time: Duration::from_secs_f32(
+                layer.map_or(0.0, |index| self.estimate.layer_times[index]),
+            ),
+            filament_length: self.estimate.filament_length,
+            filament_weight: self.estimate.filament_weight,
+            bounding_box: self.toolpath.bounding_box,
+        }
+    }
+}
+
+/// The lines of a script, lines the parser does not understand are sent as they are
+pub fn script(script: &[String]) -> Vec<gcode::Line> {
+    script
+        .iter()
+        .map(|line| gcode::Line::parse(line).unwrap_or_else(|_| gcode::Line::raw(line)))
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A running job with all its lines handed to the queue
+    fn running(text: &str) -> PrintJob {
+        let mut job = PrintJob::parse(
+            "test.gcode".to_string(),
+            text,
+            &estimate::MachineLimits::default(),
+            &estimate::Filament::default(),
+        );
+        job.start();
+        for _ in 0..job.lines().len() {
+            job.next_line();
+        }
+        job
+    }
+
+    fn codes(lines: Vec<gcode::Line>) -> Vec<String> {
+        lines.iter().map(|line| line.code()).collect()
+    }
+
+    #[test]
+    fn pause_parked() {
+        let mut job = running("G90\nM82\nG1 X10 Y20 Z0.3 F1200\nG1 X20 E5 F1500\n");
+        let temperatures = report::TemperatureReport::parse("T:205.0 /210.0 B:59.0 /60.0");
+        let options = JobOptions::default();
+
+        assert_eq!(
+            codes(job.pause(&options, temperatures)),
+            vec![
+                "M83",
+                "G1 E-2 F2100",
+                "G91",
+                "G1 Z5 F3000",
+                "G90",
+                "G1 X0 Y0 F3000"
+            ]
+        );
+        assert_eq!(job.status().state, JobState::Paused);
+        assert!(job.next_line().is_none());
+
+        assert_eq!(
+            codes(job.resume(&options)),
+            vec![
+                "M109 T0 S210",
+                "M190 S60",
+                "G90",
+                "G1 X20 Y20 F3000",
+                "G1 Z0.3 F3000",
+                "M83",
+                "G1 E2 F2100",
+                "G92 E5",
+                "G90",
+                "M82",
+                "G1 F1500"
+            ]
+        );
+        assert!(job.is_running());
+    }
+
+    #[test]
+    fn pause_in_place() {
+        let mut job = running("G1 X10 Y20 Z0.3 F1200\nG91\nG1 X5 E1\n");
+        let options = JobOptions {
+            park_on_pause: false,
+            ..JobOptions::default()
+        };
+        assert!(job.pause(&options, None).is_empty());
+        // G91 also made the extruder relative
+        assert_eq!(
+            codes(job.resume(&options)),
+            vec!["G92 E1", "G91", "M83", "G1 F1200"]
+        );
+    }
+
+    #[test]
+    fn pause_relative_extrusion() {
+        let mut job = running("M83\nG1 X10 E1 F1200\nG1 X20 E1.5\n");
+        let options = JobOptions::default();
+        job.pause(&options, None);
+        // The extruder ends in relative mode at the sum of all moves
+        let lines = codes(job.resume(&options));
+        assert_eq!(
+            lines[lines.len() - 4..],
+            ["G92 E2.5", "G90", "M83", "G1 F1200"]
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        let mut job = running("G1 X10 E1\n");
+        let temperatures = report::TemperatureReport::parse("T:205.0 /210.0 B:59.0 /60.0");
+        assert_eq!(
+            codes(job.cancel(&JobOptions::default(), temperatures.as_ref())),
+            vec![
+                "G91",
+                "G1 Z10 F600",
+                "G90",
+                "M84",
+                "M104 T0 S0",
+                "M140 S0",
+                "M107"
+            ]
+        );
+        assert_eq!(job.status().state, JobState::Cancelled);
+        // Nothing more once cancelled
+        assert!(job.cancel(&JobOptions::default(), None).is_empty());
+    }
+
+    #[test]
+    fn finish_after_last_ok() {
+        let mut job = running("G1 X10\nG1 X20\n");
+        assert!(job.next_line().is_none());
+        job.acknowledge(0);
+        assert!(!job.finish_if_done());
+        assert_eq!(job.status().state, JobState::Running);
+        job.acknowledge(1);
+        assert!(job.finish_if_done());
+        assert_eq!(job.status().state, JobState::Finished);
+        assert!(!job.finish_if_done());
+
+        // Nothing to wait for in an empty file
+        let mut job = running("; only comments\n");
+        assert!(job.finish_if_done());
+    }
+
+    #[test]
+    fn scripts() {
+        let lines: Vec<String> = ["G28 ; home", "", "G1 X1.2.3", "M84"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(codes(script(&lines)), vec!["G28", "G1 X1.2.3", "M84"]);
+    }
+}
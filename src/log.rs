@@ -1,20 +1,34 @@
 use gtk::{
     BoxExt, ButtonExt, ContainerExt, EntryExt, ScrolledWindowExt, StyleContextExt, TextBufferExt,
-    TextViewExt, WidgetExt,
+    TextViewExt, ToggleButtonExt, WidgetExt,
 };
 use relm::Relm;
 use relm_derive::Msg;
+use std::collections::VecDeque;
+
+use crate::gcode;
+use crate::queue;
+use crate::response;
 
 #[derive(Debug, Msg)]
 pub enum Msg {
     LogLine(String),
-    SendCommand(String),
+    LogSent(queue::SentLine),
+    /// The command queue started over, no line waits for its ok anymore
+    ResetQueue,
+    SubmitCommand(String),
+    SendCommand(gcode::Line),
+    SetHidePolling(bool),
     ClearLog,
     KeyInSendCmd(gdk::EventKey),
 }
 
 pub struct Model {
     stream: relm::EventStream<Msg>,
+    /// Hide the periodic temperature and position requests and their answers
+    hide_polling: bool,
+    /// For every sent line waiting for its ok, whether it is a polling request
+    polling_in_flight: VecDeque<bool>,
 }
 
 struct GtkWidgets {
@@ -36,6 +50,8 @@ impl relm::Update for Widget {
     fn model(relm: &Relm<Self>, _param: Self::ModelParam) -> Self::Model {
         Model {
             stream: relm.stream().clone(),
+            hide_polling: true,
+            polling_in_flight: VecDeque::new(),
         }
     }
 
@@ -44,26 +60,37 @@ impl relm::Update for Widget {
             Msg::KeyInSendCmd(key) => {
                 if let Some(key_name) = key.get_keyval().name() {
                     if key_name == "Return" {
-                        self.model.stream.emit(Msg::SendCommand(
+                        self.model.stream.emit(Msg::SubmitCommand(
                             self.widgets.send_cmd.get_text().to_string(),
                         ));
                     };
                 };
             }
             Msg::LogLine(text) => {
-                // Get current time
-                let time = chrono::Local::now().format("%H:%M:%S%.3f");
-                // Append Message
-                let mut end_iter = self.widgets.textview.get_buffer().unwrap().get_end_iter();
-                self.widgets
-                    .textview
-                    .get_buffer()
-                    .unwrap()
-                    .insert(&mut end_iter, &format!("{} -> {}\n", time, text));
-                self.widgets
-                    .textview
-                    .scroll_to_iter(&mut end_iter, 0.0, false, 0.0, 0.0);
+                let polling = match response::Response::parse(&text) {
+                    response::Response::Temperature(_) | response::Response::Position(_) => true,
+                    // The lines are acknowledged in the order they were sent
+                    response::Response::Ok(payload) => {
+                        let acknowledged = self.model.polling_in_flight.pop_front();
+                        acknowledged == Some(true) || payload.contains("T:")
+                    }
+                    _ => false,
+                };
+                if polling && self.model.hide_polling {
+                    return;
+                }
+                self.append(&format!("-> {}", text));
+            }
+            Msg::LogSent(line) => {
+                let polling = line.command.is('M', 105) || line.command.is('M', 114);
+                self.model.polling_in_flight.push_back(polling);
+                if polling && self.model.hide_polling {
+                    return;
+                }
+                self.append(&format!("<- {}", line.text));
             }
+            Msg::ResetQueue => self.model.polling_in_flight.clear(),
+            Msg::SetHidePolling(hide) => self.model.hide_polling = hide,
             Msg::ClearLog => {
                 let mut start = self.widgets.textview.get_buffer().unwrap().get_start_iter();
                 let mut end = self.widgets.textview.get_buffer().unwrap().get_end_iter();
@@ -73,13 +100,35 @@ impl relm::Update for Widget {
                     .unwrap()
                     .delete(&mut start, &mut end)
             }
-            Msg::SendCommand(_text) => {
+            Msg::SubmitCommand(text) => match gcode::Line::parse(&text) {
+                Ok(line) => self.model.stream.emit(Msg::SendCommand(line)),
+                Err(err) => self.append(&format!("Invalid G-code \"{}\": {}", text, err)),
+            },
+            Msg::SendCommand(_line) => {
                 self.widgets.send_cmd.set_text("");
             }
         }
     }
 }
 
+impl Widget {
+    /// Append a line with the current time to the log
+    fn append(&self, text: &str) {
+        // Get current time
+        let time = chrono::Local::now().format("%H:%M:%S%.3f");
+        // Append Message
+        let mut end_iter = self.widgets.textview.get_buffer().unwrap().get_end_iter();
+        self.widgets
+            .textview
+            .get_buffer()
+            .unwrap()
+            .insert(&mut end_iter, &format!("{} {}\n", time, text));
+        self.widgets
+            .textview
+            .scroll_to_iter(&mut end_iter, 0.0, false, 0.0, 0.0);
+    }
+}
+
 impl relm::Widget for Widget {
     type Root = gtk::Box;
 
@@ -117,6 +166,10 @@ impl relm::Widget for Widget {
             .add_class("destructive-action");
         hbox.pack_start(&clear_btn, false, false, 3);
 
+        let hide_polling_btn = gtk::CheckButton::with_label("Hide polling");
+        hide_polling_btn.set_active(true);
+        hbox.pack_start(&hide_polling_btn, false, false, 3);
+
         root_box.pack_start(&hbox, false, false, 3);
 
         let send_cmd_clone = send_cmd.clone();
@@ -124,9 +177,15 @@ impl relm::Widget for Widget {
             relm,
             send_btn,
             connect_clicked(_),
-            Msg::SendCommand(send_cmd_clone.get_text().to_string())
+            Msg::SubmitCommand(send_cmd_clone.get_text().to_string())
         );
         relm::connect!(relm, clear_btn, connect_clicked(_), Msg::ClearLog);
+        relm::connect!(
+            relm,
+            hide_polling_btn,
+            connect_toggled(btn),
+            Msg::SetHidePolling(btn.get_active())
+        );
         relm::connect!(
             relm,
             send_cmd,
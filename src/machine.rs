@@ -0,0 +1,244 @@
+use crate::gcode;
+
+/// The state of the printer as far as it follows from the G-code sent to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MachineState {
+    /// Logical position of X, Y, Z and E
+    pub position: [f32; 4],
+    /// Feedrate in mm/min
+    pub feedrate: f32,
+    /// G90 / G91
+    pub absolute: bool,
+    /// M82 / M83
+    pub absolute_e: bool,
+}
+
+impl Default for MachineState {
+    fn default() -> Self {
+        MachineState {
+            position: [0.0; 4],
+            feedrate: 1500.0,
+            absolute: true,
+            absolute_e: true,
+        }
+    }
+}
+
+const AXES: [char; 4] = ['X', 'Y', 'Z', 'E'];
+
+/// Length in mm of the straight pieces an arc is split into
+const ARC_SEGMENT_LENGTH: f32 = 1.0;
+
+/// True for straight moves and arcs
+pub fn is_move(line: &gcode::Line) -> bool {
+    line.is('G', 0) || line.is('G', 1) || line.is('G', 2) || line.is('G', 3)
+}
+
+/// The points a move from `start` to `end` passes, without the start.
+/// Arcs of `G2`/`G3` in the XY plane are split into short straight pieces,
+/// any other move goes straight to the end.
+pub fn arc_points(line: &gcode::Line, start: &[f32; 4], end: &[f32; 4]) -> Vec<[f32; 4]> {
+    let clockwise = line.is('G', 2);
+    if !(clockwise || line.is('G', 3)) {
+        return vec![*end];
+    }
+    let center = match (line.get('I'), line.get('J'), line.get('R')) {
+        (None, None, Some(radius)) => {
+            // The center is on the bisector of the chord, a negative radius takes the long way
+            let radius = radius as f32;
+            let (dx, dy) = (end[0] - start[0], end[1] - start[1]);
+            let chord = (dx * dx + dy * dy).sqrt();
+            if chord <= 0.0 {
+                return vec![*end];
+            }
+            let h = (radius * radius - chord * chord / 4.0).max(0.0).sqrt();
+            let side = if clockwise ^ (radius < 0.0) {
+                -1.0
+            } else {
+                1.0
+            };
+            [
+                (start[0] + end[0]) / 2.0 - side * h * dy / chord,
+                (start[1] + end[1]) / 2.0 + side * h * dx / chord,
+            ]
+        }
+        (None, None, None) => return vec![*end],
+        (i, j, _) => [
+            start[0] + i.unwrap_or(0.0) as f32,
+            start[1] + j.unwrap_or(0.0) as f32,
+        ],
+    };
+
+    let radius = (start[0] - center[0]).hypot(start[1] - center[1]);
+    let start_angle = (start[1] - center[1]).atan2(start[0] - center[0]);
+    let end_angle = (end[1] - center[1]).atan2(end[0] - center[0]);
+    let tau = std::f32::consts::PI * 2.0;
+    let mut sweep = end_angle - start_angle;
+    if sweep < 0.0 {
+        sweep += tau;
+    }
+    if clockwise {
+        sweep -= tau;
+    } else if sweep == 0.0 {
+        // Ending where it started is a full circle
+        sweep = tau;
+    }
+
+    let pieces = ((sweep.abs() * radius) / ARC_SEGMENT_LENGTH)
+        .ceil()
+        .max(1.0) as usize;
+    let mut points: Vec<[f32; 4]> = (1..pieces)
+        .map(|piece| {
+            let share = piece as f32 / pieces as f32;
+            let angle = start_angle + sweep * share;
+            [
+                center[0] + radius * angle.cos(),
+                center[1] + radius * angle.sin(),
+                start[2] + (end[2] - start[2]) * share,
+                start[3] + (end[3] - start[3]) * share,
+            ]
+        })
+        .collect();
+    points.push(*end);
+    points
+}
+
+impl MachineState {
+    /// Follow a single line of G-code
+    pub fn apply(&mut self, line: &gcode::Line) {
+        let command = match line.command {
+            Some(command) => command,
+            None => return,
+        };
+
+        match (command.letter, command.number) {
+            // Arcs end at their X, Y, Z and E just like straight moves
+            ('G', 0) | ('G', 1) | ('G', 2) | ('G', 3) => {
+                for (axis, name) in AXES.iter().enumerate() {
+                    if let Some(value) = line.get(*name) {
+                        let absolute = if axis == 3 {
+                            self.absolute_e
+                        } else {
+                            self.absolute
+                        };
+                        if absolute {
+                            self.position[axis] = value as f32;
+                        } else {
+                            self.position[axis] += value as f32;
+                        }
+                    }
+                }
+                if let Some(feedrate) = line.get('F') {
+                    self.feedrate = feedrate as f32;
+                }
+            }
+            // Homing without axes homes X, Y and Z
+            ('G', 28) => {
+                let homes_all = !AXES[..3].iter().any(|name| line.has(*name));
+                for (axis, name) in AXES[..3].iter().enumerate() {
+                    if homes_all || line.has(*name) {
+                        self.position[axis] = 0.0;
+                    }
+                }
+            }
+            // G91 also switches the extruder to relative mode on Marlin
+            ('G', 90) => {
+                self.absolute = true;
+                self.absolute_e = true;
+            }
+            ('G', 91) => {
+                self.absolute = false;
+                self.absolute_e = false;
+            }
+            ('G', 92) => {
+                for (axis, name) in AXES.iter().enumerate() {
+                    if let Some(value) = line.get(*name) {
+                        self.position[axis] = value as f32;
+                    }
+                }
+            }
+            ('M', 82) => self.absolute_e = true,
+            ('M', 83) => self.absolute_e = false,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(lines: &[&str]) -> MachineState {
+        let mut machine = MachineState::default();
+        for line in lines {
+            machine.apply(&gcode::Line::parse(line).unwrap());
+        }
+        machine
+    }
+
+    #[test]
+    fn moves_and_modes() {
+        let machine = apply(&[
+            "G28",
+            "G1 X10 Y20 Z0.2 E1 F1200",
+            "G91",
+            "G1 X5 E0.5",
+            "G90",
+        ]);
+        assert_eq!(machine.position, [15.0, 20.0, 0.2, 1.5]);
+        assert_eq!(machine.feedrate, 1200.0);
+        assert!(machine.absolute && machine.absolute_e);
+
+        let machine = apply(&["M83", "G1 X10 E1", "G1 X20 E1", "G92 E0"]);
+        assert_eq!(machine.position, [20.0, 0.0, 0.0, 0.0]);
+        assert!(machine.absolute && !machine.absolute_e);
+    }
+
+    #[test]
+    fn arcs() {
+        let machine = apply(&[
+            "G1 X10 Y0 E1 F1200",
+            "G2 X10 Y20 I0 J10 E3 F900",
+            "G3 X0 Y20 R5 E4",
+        ]);
+        assert_eq!(machine.position, [0.0, 20.0, 0.0, 4.0]);
+        assert_eq!(machine.feedrate, 900.0);
+    }
+
+    #[test]
+    fn arc_points() {
+        let length = |points: &[[f32; 4]], start: [f32; 4]| {
+            let mut from = start;
+            let mut length = 0.0;
+            for to in points {
+                length += (to[0] - from[0]).hypot(to[1] - from[1]);
+                from = *to;
+            }
+            length
+        };
+        let line = |code: &str| gcode::Line::parse(code).unwrap();
+        let start = [10.0, 0.0, 0.2, 1.0];
+        let end = [10.0, 20.0, 0.2, 3.0];
+        let half_circle = std::f32::consts::PI * 10.0;
+
+        // Clockwise around X10 Y10 passes X0, counterclockwise passes X20
+        let points = super::arc_points(&line("G2 X10 Y20 I0 J10 E3"), &start, &end);
+        assert_eq!(*points.last().unwrap(), end);
+        assert!((length(&points, start) - half_circle).abs() < 0.05);
+        let middle = points[points.len() / 2 - 1];
+        assert!(middle[0] < 0.1 && (middle[1] - 10.0).abs() < 1.0);
+        assert!((middle[3] - 2.0).abs() < 0.1);
+        let points = super::arc_points(&line("G3 X10 Y20 R10 E3"), &start, &end);
+        assert!(points.iter().all(|point| point[0] >= 10.0 - 1e-3));
+        assert!(points.iter().any(|point| point[0] > 19.9));
+
+        // Back to the start is a full circle
+        let points = super::arc_points(&line("G2 X10 Y0 J10"), &start, &start);
+        assert!((length(&points, start) - half_circle * 2.0).abs() < 0.1);
+
+        assert_eq!(
+            super::arc_points(&line("G1 X10 Y20"), &start, &end),
+            vec![end]
+        );
+    }
+}
@@ -2,19 +2,50 @@ use gtk::prelude::*;
 use relm::{connect, Component, ContainerWidget, Relm, Update, Widget};
 use relm_derive::Msg;
 
+mod config;
 mod connection;
 mod control;
+mod dialect;
+mod estimate;
+mod firmware;
+mod gcode;
+mod job;
 mod log;
+mod machine;
+mod network;
+mod preview;
+mod print;
+mod queue;
+mod report;
+mod response;
+mod settings;
+mod toolpath;
+mod transport;
+mod virtual_printer;
 
 #[derive(Debug, Clone, Msg)]
 enum Msg {
     Quit,
-    EnqueueCommand(String),
+    EnqueueCommand(gcode::Line),
     ClearCommandQueue,
+    CancelJob,
+    SetLineNumbers(bool),
+    SetStreamingMode(queue::StreamingMode),
     EvalResponse(String),
+    BaudRateDetected(Option<u32>),
+    Connecting(String, u32, Option<String>),
+    SelectProfile(String),
+    SetConfig(config::Config),
     SendCommand,
     Connect,
     Disconnect,
+    LoadJob(std::path::PathBuf),
+    StartJob,
+    PauseJob,
+    ResumeJob,
+    SetParkOnPause(bool),
+    JobTick,
+    EmergencyStop,
 }
 
 struct Win {
@@ -22,14 +53,33 @@ struct Win {
     _manual_control: Component<control::Widget>,
     _connection_control: Component<connection::Widget>,
     _logging: Component<log::Widget>,
+    _printing: Component<print::Widget>,
+    _settings: Component<settings::Widget>,
     _port: Option<Box<dyn serialport::SerialPort>>,
+    header_bar: gtk::HeaderBar,
     window: gtk::Window,
 }
 
 struct Model {
-    command_queue: std::collections::VecDeque<String>,
+    config: config::Config,
+    command_queue: queue::CommandQueue,
+    job: Option<job::PrintJob>,
+    job_options: job::JobOptions,
+    /// Limits for the print time estimate, updated from the `M503` report
+    machine_limits: estimate::MachineLimits,
+    /// `M503` reported new limits, the job is estimated again once the report is complete
+    limits_changed: bool,
+    /// The filament of the profile
+    filament: estimate::Filament,
+    /// The last temperature report, needed to restore the heaters after a pause
+    temperatures: Option<report::TemperatureReport>,
+    /// The answer to `M115` of the connected printer
+    capabilities: firmware::Capabilities,
+    /// The flavour of the profile or the one detected from `M115`
+    dialect: Box<dyn dialect::Dialect>,
     connected: bool,
-    waiting_for_ok: bool,
+    /// Why the config file could not be read, shown in the log once it exists
+    config_error: Option<String>,
     relm: Relm<Win>,
 }
 
@@ -38,12 +88,30 @@ impl Update for Win {
     type ModelParam = ();
     type Msg = Msg;
 
+    fn subscriptions(&mut self, relm: &Relm<Self>) {
+        relm::interval(relm.stream(), 1000, || Msg::JobTick);
+    }
+
     fn model(relm: &Relm<Self>, _param: Self::ModelParam) -> Self::Model {
+        let (config, config_error) = config::Config::load();
+        let mut job_options = job::JobOptions::default();
+        set_scripts(&mut job_options, config.profile());
+        let dialect = dialect::for_flavour(config.profile().flavour);
+        let filament = filament(config.profile());
         Model {
-            command_queue: std::collections::VecDeque::new(),
+            config,
+            command_queue: queue::CommandQueue::default(),
+            job: None,
+            job_options,
+            machine_limits: estimate::MachineLimits::default(),
+            limits_changed: false,
+            filament,
+            temperatures: None,
+            capabilities: firmware::Capabilities::default(),
+            dialect,
             relm: relm.clone(),
-            waiting_for_ok: false,
             connected: false,
+            config_error,
         }
     }
 
@@ -51,60 +119,502 @@ impl Update for Win {
         match event {
             Msg::Connect => {
                 self.model.connected = true;
-                self.model.relm.stream().emit(Msg::ClearCommandQueue);
+                // Starts with M110 if line numbers are used
+                self.model.command_queue.reset();
+                self._logging.emit(log::Msg::ResetQueue);
+                // Ask the firmware what it can do
+                self.set_capabilities(firmware::Capabilities::default());
+                let flavour = self.model.config.profile().flavour;
+                self.set_flavour(flavour);
+                self.model
+                    .command_queue
+                    .push(gcode::Line::command('M', 115));
+                // A detected dialect sends its queries once the answer arrived
+                if flavour != config::Flavour::Auto {
+                    self.push_queries();
+                }
+                self.model.relm.stream().emit(Msg::SendCommand);
             }
             Msg::Disconnect => {
                 self.model.connected = false;
+                self.model.relm.stream().emit(Msg::ClearCommandQueue);
+                self.set_capabilities(firmware::Capabilities::default());
+                self.stop_job("the printer was disconnected");
+            }
+            Msg::BaudRateDetected(baud_rate) => {
+                self._logging.emit(log::Msg::LogLine(match baud_rate {
+                    Some(baud_rate) => format!("Printer found at {} baud", baud_rate),
+                    None => "No printer answered at any baud rate".to_string(),
+                }));
+                // Skip the detection next time
+                if let Some(baud_rate) = baud_rate {
+                    self.model.config.profile_mut().baud_rate = baud_rate;
+                    self.config_changed();
+                }
+            }
+            Msg::Connecting(port, baud_rate, serial_number) => {
+                let profile = self.model.config.profile_mut();
+                profile.port = Some(port);
+                profile.baud_rate = baud_rate;
+                // The profile follows the printer to whatever port it gets next time.
+                // A bound profile keeps its printer, another one may be connected on purpose.
+                match profile.serial_number.clone() {
+                    None => profile.serial_number = serial_number,
+                    Some(bound) => {
+                        if let Some(serial_number) = serial_number.filter(|sn| *sn != bound) {
+                            self._logging.emit(log::Msg::LogLine(format!(
+                                "The printer has the serial number {}, the profile is kept for {}",
+                                serial_number, bound
+                            )));
+                        }
+                    }
+                }
+                self.config_changed();
+            }
+            Msg::SelectProfile(name) => {
+                if name != self.model.config.profile().name {
+                    self.model.config.active_profile = name;
+                    self.apply_profile();
+                    self.config_changed();
+                }
+            }
+            Msg::SetConfig(config) => {
+                let polling_changed = config.polling != self.model.config.polling;
+                self.model.config = config;
+                self._connection_control.emit(connection::Msg::SetProfiles(
+                    self.model.config.profile_names(),
+                    self.model.config.profile().name.clone(),
+                ));
+                self.apply_profile();
+                self._connection_control
+                    .emit(connection::Msg::SetSerialSettings(
+                        connection::SerialSettings::from(&self.model.config.serial),
+                    ));
+                self._manual_control
+                    .emit(control::Msg::SetConfig(self.model.config.clone()));
+                // The firmware sends the reports at the new intervals
+                if polling_changed && self.model.connected {
+                    self.set_auto_report();
+                }
+                apply_theme(&self.model.config.ui);
+                self.save_config();
             }
             Msg::EnqueueCommand(command) => {
+                if let Err(err) = self.model.config.profile().check_temperature(&command) {
+                    self._logging.emit(log::Msg::LogLine(format!(
+                        "Not sending {}: {}",
+                        command.code(),
+                        err
+                    )));
+                    return;
+                }
                 if self.model.connected {
-                    self.model.command_queue.push_back(command);
+                    self.model.command_queue.push(command);
                     self.model.relm.stream().emit(Msg::SendCommand);
                 }
             }
             Msg::SendCommand => {
-                // Check if we are currently waiting for a response
-                if !self.model.waiting_for_ok & self.model.connected {
-                    // Is something in the queue?
-                    if !self.model.command_queue.is_empty() {
-                        let command = self.model.command_queue.pop_front().unwrap();
-                        self._connection_control
-                            .emit(connection::Msg::SendLine(command.clone()));
-                        self._logging.emit(log::Msg::LogLine(command));
-                        self.model.waiting_for_ok = true;
+                if self.model.connected {
+                    loop {
+                        // The queue only hands out lines as long as the printer is ready for them
+                        while let Some(line) = self.model.command_queue.next_line() {
+                            self._connection_control
+                                .emit(connection::Msg::SendLine(line.text.clone()));
+                            if let Some(job_line) = line.job_line {
+                                self._printing.emit(print::Msg::LineSent(job_line));
+                            }
+                            self._logging.emit(log::Msg::LogSent(line));
+                        }
+                        if !self.model.command_queue.is_empty() {
+                            break;
+                        }
+                        // Feed the queue from the print job once it ran dry
+                        let job = match self.model.job.as_mut() {
+                            Some(job) => job,
+                            None => break,
+                        };
+                        match job.next_line() {
+                            Some((index, line)) => {
+                                self.model.command_queue.push_job_line(line, index)
+                            }
+                            // The end script follows once the last line was acknowledged
+                            None if job.finish_if_done() => {
+                                for line in job::script(&self.model.job_options.end_script) {
+                                    self.model.command_queue.push(line);
+                                }
+                                self._printing.emit(print::Msg::SetStatus(job.status()));
+                            }
+                            None => break,
+                        }
+                    }
+                }
+            }
+            Msg::EvalResponse(response) => match self.parse_response(&response) {
+                response::Response::Ok(payload) => {
+                    // Klipper answers M115 in the ok
+                    self.parse_capabilities(&payload);
+                    if self.model.dialect.position_in_ok() {
+                        if let Some(report) = report::PositionReport::parse(&payload) {
+                            self.set_position(report);
+                        }
+                    }
+                    // M105 reports the temperatures together with the ok
+                    if let Some(report) = report::TemperatureReport::parse(&payload) {
+                        self.model.temperatures = Some(report.clone());
+                        self._manual_control
+                            .emit(control::Msg::SetTemperature(report));
+                    }
+                    if let Some(line) = self.model.command_queue.acknowledge(&payload) {
+                        if let Some(job_line) = line.job_line {
+                            if let Some(ref mut job) = self.model.job {
+                                job.acknowledge(job_line);
+                            }
+                            self._printing.emit(print::Msg::LineAcknowledged(job_line));
+                        }
+                        // The whole M503 report arrived before its ok
+                        if line.command.is('M', 503) && self.model.limits_changed {
+                            self.model.limits_changed = false;
+                            self.update_estimate();
+                        }
+                    }
+                    // Send new command
+                    self.model.relm.stream().emit(Msg::SendCommand);
+                }
+                // Temperatures arrive on their own while heating up
+                response::Response::Temperature(report) => {
+                    self.model.temperatures = Some(report.clone());
+                    self._manual_control
+                        .emit(control::Msg::SetTemperature(report));
+                }
+                // M114 reports the position on its own line before the ok
+                response::Response::Position(report) => self.set_position(report),
+                // The firmware was reset, the command in flight will never be acknowledged
+                response::Response::Start => {
+                    self.model.command_queue.reset();
+                    self._logging.emit(log::Msg::ResetQueue);
+                    self.stop_job("the printer was reset");
+                    self.model.relm.stream().emit(Msg::SendCommand);
+                }
+                // The lines are sent again once the following ok arrived
+                response::Response::Resend(line_number) => {
+                    if !self.model.command_queue.resend(line_number) {
+                        self._logging.emit(log::Msg::LogLine(format!(
+                            "Cannot resend line {}, it is not in the history",
+                            line_number
+                        )));
                     }
                 }
+                // M503 reports the settings as G-code
+                response::Response::Echo(text) => {
+                    if let Ok(line) = gcode::Line::parse(&text) {
+                        if self.model.machine_limits.apply(&line) {
+                            self.model.limits_changed = true;
+                        }
+                    }
+                }
+                // The answer to M115
+                response::Response::Unknown(text) => self.parse_capabilities(&text),
+                response::Response::Busy(_)
+                | response::Response::Error(_)
+                | response::Response::Wait
+                | response::Response::Action(_) => (),
+            },
+            Msg::ClearCommandQueue => self.model.command_queue.clear(),
+            Msg::CancelJob => {
+                // The lines of the job waiting in the queue are not sent anymore
+                self.model.command_queue.clear();
+                if let Some(ref mut job) = self.model.job {
+                    for line in
+                        job.cancel(&self.model.job_options, self.model.temperatures.as_ref())
+                    {
+                        self.model.command_queue.push(line);
+                    }
+                    self._printing.emit(print::Msg::SetStatus(job.status()));
+                }
+                self.model.relm.stream().emit(Msg::SendCommand);
             }
-            Msg::EvalResponse(response) => {
-                // Are we waiting for a response?
-                if self.model.waiting_for_ok {
-                    let maybe_ok = response.split_at(2);
-                    if maybe_ok.0 == "ok" {
-                        self.model.waiting_for_ok = false;
-                        // Check if it is answer to temperature or position
-                        match maybe_ok.1.chars().nth(0) {
-                            Some('T') => self
-                                ._manual_control
-                                .emit(control::Msg::SetTemperature(maybe_ok.1.to_string())),
-                            Some('X') => self
-                                ._manual_control
-                                .emit(control::Msg::SetTemperature(maybe_ok.1.to_string())),
-                            _ => (),
+            Msg::SetLineNumbers(enabled) => self.model.command_queue.set_line_numbers(enabled),
+            Msg::SetStreamingMode(mode) => self.model.command_queue.set_mode(mode),
+            Msg::LoadJob(path) => {
+                if self.model.job.as_ref().map_or(false, |job| job.is_active()) {
+                    self._printing.emit(print::Msg::SetError(
+                        "Cancel the running job before loading another file".to_string(),
+                    ));
+                    return;
+                }
+                match job::PrintJob::load(&path, &self.model.machine_limits, &self.model.filament) {
+                    Ok(job) => {
+                        self._printing.emit(print::Msg::SetStatus(job.status()));
+                        self._printing.emit(print::Msg::SetToolpath(job.toolpath()));
+                        self.model.job = Some(job);
+                    }
+                    Err(err) => self._printing.emit(print::Msg::SetError(format!(
+                        "Cannot open {}: {}",
+                        path.display(),
+                        err
+                    ))),
+                }
+            }
+            Msg::StartJob => {
+                if self.model.connected {
+                    if let Some(ref mut job) = self.model.job {
+                        if !job.is_running() {
+                            // The lines of a job are checked like the commands of the console
+                            let profile = self.model.config.profile();
+                            let error = job.lines().iter().enumerate().find_map(|(index, line)| {
+                                profile
+                                    .check_temperature(line)
+                                    .err()
+                                    .map(|err| format!("line {}: {}", index + 1, err))
+                            });
+                            if let Some(error) = error {
+                                self._printing.emit(print::Msg::SetError(format!(
+                                    "Not starting the job, {}",
+                                    error
+                                )));
+                                return;
+                            }
+                            for line in job::script(&self.model.job_options.start_script) {
+                                self.model.command_queue.push(line);
+                            }
+                            job.start();
+                            self._printing.emit(print::Msg::SetStatus(job.status()));
+                            self._printing.emit(print::Msg::ResetProgress);
+                            self.model.relm.stream().emit(Msg::SendCommand);
                         }
-                        // Send new command
-                        self.model.relm.stream().emit(Msg::SendCommand);
                     }
                 }
             }
-            Msg::ClearCommandQueue => {
+            Msg::PauseJob => {
+                if let Some(ref mut job) = self.model.job {
+                    for line in job.pause(&self.model.job_options, self.model.temperatures.clone())
+                    {
+                        self.model.command_queue.push(line);
+                    }
+                    self._printing.emit(print::Msg::SetStatus(job.status()));
+                    self.model.relm.stream().emit(Msg::SendCommand);
+                }
+            }
+            Msg::ResumeJob => {
+                if let Some(ref mut job) = self.model.job {
+                    for line in job.resume(&self.model.job_options) {
+                        self.model.command_queue.push(line);
+                    }
+                    self._printing.emit(print::Msg::SetStatus(job.status()));
+                    self.model.relm.stream().emit(Msg::SendCommand);
+                }
+            }
+            Msg::SetParkOnPause(park) => self.model.job_options.park_on_pause = park,
+            Msg::EmergencyStop => {
+                if !self.model.connected {
+                    return;
+                }
+                // Nothing may follow, the cancel script of a running job is dropped as well
                 self.model.command_queue.clear();
-                self.model.waiting_for_ok = false;
+                if let Some(ref mut job) = self.model.job {
+                    job.cancel(&self.model.job_options, None);
+                    self._printing.emit(print::Msg::SetStatus(job.status()));
+                }
+                // Bypass the queue, the firmware may not accept more lines right now
+                self._connection_control
+                    .emit(connection::Msg::SendLine("M112".to_string()));
+                let dialect = &self.model.dialect;
+                self._logging.emit(log::Msg::LogLine(
+                    if dialect.immediate_emergency_stop(&self.model.capabilities) {
+                        format!("Emergency stop sent. {}", dialect.emergency_stop_recovery())
+                    } else {
+                        format!(
+                            "Emergency stop sent, the firmware only reads it after the commands \
+                             in its buffer. {}",
+                            dialect.emergency_stop_recovery()
+                        )
+                    },
+                ));
+            }
+            Msg::JobTick => {
+                if let Some(ref job) = self.model.job {
+                    let status = job.status();
+                    // Show the progress in the header bar so it is visible from every tab
+                    let subtitle = match (status.state, status.remaining) {
+                        (job::JobState::Running, Some(remaining))
+                        | (job::JobState::Paused, Some(remaining)) => Some(format!(
+                            "{}{} - {:.0}% - {} left",
+                            status.name,
+                            if status.state == job::JobState::Paused {
+                                " (paused)"
+                            } else {
+                                ""
+                            },
+                            status.progress.time * 100.0,
+                            print::format_duration(remaining)
+                        )),
+                        _ => None,
+                    };
+                    self.header_bar.set_subtitle(subtitle.as_deref());
+                    self._printing.emit(print::Msg::SetStatus(status));
+                }
             }
             Msg::Quit => gtk::main_quit(),
         }
     }
 }
 
+impl Win {
+    /// Show a config changed here in the Settings tab and save it
+    fn config_changed(&self) {
+        self._settings
+            .emit(settings::Msg::Update(self.model.config.clone()));
+        self.save_config();
+    }
+
+    /// Let the rest of the app follow the active printer profile
+    fn apply_profile(&mut self) {
+        let profile = self.model.config.profile().clone();
+        set_scripts(&mut self.model.job_options, &profile);
+        let filament = filament(&profile);
+        if filament != self.model.filament {
+            self.model.filament = filament;
+            self.update_estimate();
+        }
+        // Keep the dialect detected from the connected printer
+        if profile.flavour != config::Flavour::Auto
+            || self.model.capabilities.firmware_name.is_none()
+        {
+            self.set_flavour(profile.flavour);
+        }
+        self._printing
+            .emit(print::Msg::SetBuildVolume(profile.build_volume));
+        self._manual_control
+            .emit(control::Msg::SetProfile(profile.clone()));
+        self._connection_control
+            .emit(connection::Msg::SetProfile(profile));
+    }
+
+    /// Estimate the loaded job again with the current limits and filament
+    fn update_estimate(&mut self) {
+        if let Some(ref mut job) = self.model.job {
+            job.update_estimate(&self.model.machine_limits, &self.model.filament);
+            self._printing.emit(print::Msg::SetStatus(job.status()));
+        }
+    }
+
+    /// Cancel a running job without sending anything, the printer lost its state.
+    /// The job is never continued on its own after a reconnect.
+    fn stop_job(&mut self, reason: &str) {
+        if let Some(ref mut job) = self.model.job {
+            if job.is_active() {
+                job.cancel(&self.model.job_options, None);
+                self._printing.emit(print::Msg::SetStatus(job.status()));
+                self._logging.emit(log::Msg::LogLine(format!(
+                    "The print job was cancelled, {}",
+                    reason
+                )));
+            }
+        }
+    }
+
+    /// Take a line that may be part of the answer to `M115`
+    fn parse_capabilities(&mut self, line: &str) {
+        let mut capabilities = self.model.capabilities.clone();
+        if !capabilities.parse_line(line) {
+            return;
+        }
+        let detected = match capabilities.firmware_name {
+            Some(ref name) if self.model.capabilities.firmware_name.is_none() => {
+                Some(dialect::detect(name))
+            }
+            _ => None,
+        };
+        self.set_capabilities(capabilities);
+        if let Some(detected) = detected {
+            if self.model.config.profile().flavour == config::Flavour::Auto {
+                self._logging.emit(log::Msg::LogLine(match detected {
+                    Some(flavour) => format!("Detected {} firmware", flavour.name()),
+                    None => "Unknown firmware, treating it as Marlin".to_string(),
+                }));
+                self.set_flavour(detected.unwrap_or(config::Flavour::Marlin));
+                self.push_queries();
+                self.model.relm.stream().emit(Msg::SendCommand);
+            }
+        }
+    }
+
+    /// Speak the dialect of the firmware from now on
+    fn set_flavour(&mut self, flavour: config::Flavour) {
+        self.model.dialect = dialect::for_flavour(flavour);
+        self._manual_control.emit(control::Msg::SetFlavour(flavour));
+    }
+
+    /// Ask the firmware for what its dialect needs to know, e.g. the limits of the printer
+    /// for the print time estimate
+    fn push_queries(&mut self) {
+        for line in self.model.dialect.queries() {
+            self.model.command_queue.push(line);
+        }
+    }
+
+    /// Understand a line of the firmware as if Marlin sent it
+    fn parse_response(&self, line: &str) -> response::Response {
+        response::Response::parse(&self.model.dialect.normalize(line))
+    }
+
+    fn set_position(&mut self, report: report::PositionReport) {
+        self._printing
+            .emit(print::Msg::SetHeadPosition([report.x, report.y, report.z]));
+        self._manual_control.emit(control::Msg::SetPosition(report))
+    }
+
+    fn set_capabilities(&mut self, capabilities: firmware::Capabilities) {
+        let auto_report = |capabilities: &firmware::Capabilities| {
+            (
+                capabilities.has(firmware::AUTOREPORT_TEMP),
+                capabilities.has(firmware::AUTOREPORT_POS),
+            )
+        };
+        let auto_report_changed =
+            auto_report(&capabilities) != auto_report(&self.model.capabilities);
+        self._settings
+            .emit(settings::Msg::SetCapabilities(capabilities.clone()));
+        self.model.capabilities = capabilities;
+        if auto_report_changed {
+            self.set_auto_report();
+        }
+    }
+
+    /// Let the firmware send the reports on its own instead of polling them, if it can
+    fn set_auto_report(&mut self) {
+        // Whole seconds, zero turns the report off
+        let seconds = |interval: u64| ((interval + 999) / 1000) as f64;
+        let polling = self.model.config.polling;
+        let temperature = self.model.capabilities.has(firmware::AUTOREPORT_TEMP);
+        let position = self.model.capabilities.has(firmware::AUTOREPORT_POS);
+        if self.model.connected {
+            if temperature {
+                self.model
+                    .command_queue
+                    .push(gcode::Line::command('M', 155).with('S', seconds(polling.temperature)));
+            }
+            if position {
+                self.model
+                    .command_queue
+                    .push(gcode::Line::command('M', 154).with('S', seconds(polling.position)));
+            }
+            self.model.relm.stream().emit(Msg::SendCommand);
+        }
+        self._manual_control
+            .emit(control::Msg::SetAutoReport(temperature, position));
+    }
+
+    fn save_config(&self) {
+        if let Err(err) = self.model.config.save() {
+            self._logging.emit(log::Msg::LogLine(format!(
+                "Cannot save the settings: {}",
+                err
+            )));
+        }
+    }
+}
+
 impl Widget for Win {
     type Root = gtk::Window;
 
@@ -118,7 +628,10 @@ impl Widget for Win {
         // The main Window
         let window = gtk::Window::new(gtk::WindowType::Toplevel);
         window.set_size_request(800, 600);
-        window.maximize();
+        if model.config.ui.maximized {
+            window.maximize();
+        }
+        apply_theme(&model.config.ui);
 
         // Add a header bar to the window
         let header_bar = gtk::HeaderBarBuilder::default()
@@ -130,7 +643,7 @@ impl Widget for Win {
         // Create vertical box to store Statusline and main window
         let vbox = gtk::Box::new(gtk::Orientation::Vertical, 2);
 
-        let connection_control = vbox.add_widget::<connection::Widget>(());
+        let connection_control = vbox.add_widget::<connection::Widget>(model.config.clone());
 
         // Create a notebook to have some nice tabs on the left side
         let notebook = gtk::NotebookBuilder::default()
@@ -138,15 +651,14 @@ impl Widget for Win {
             .build();
 
         // Add the manual control page
-        let manual_control = notebook.add_widget::<control::Widget>(());
+        let manual_control = notebook.add_widget::<control::Widget>(model.config.clone());
         notebook.set_tab_label(
             &notebook.get_nth_page(Some(0)).unwrap(), // Safe to unwrap because we added the 0st element just bevore
             Some(&create_tab_widget("Move")),
         );
 
         // Add Print Page
-        let printing = gtk::Label::new(Some("Printing"));
-        notebook.add(&printing);
+        let printing = notebook.add_widget::<print::Widget>(());
         notebook.set_tab_label(
             &notebook.get_nth_page(Some(1)).unwrap(), // Safe to unwrap because we added the 1st element just bevore
             Some(&create_tab_widget("Print")),
@@ -159,9 +671,12 @@ impl Widget for Win {
             Some(&create_tab_widget("Log")),
         );
 
+        if let Some(ref error) = model.config_error {
+            logging.emit(log::Msg::LogLine(error.clone()));
+        }
+
         // Add Settings Page
-        let settings = gtk::Label::new(Some("Settings"));
-        notebook.add(&settings);
+        let settings = notebook.add_widget::<settings::Widget>(model.config.clone());
         notebook.set_tab_label(
             &notebook.get_nth_page(Some(3)).unwrap(), // Safe to unwrap because we added the 2st element just bevore
             Some(&create_tab_widget("Settings")),
@@ -182,13 +697,31 @@ impl Widget for Win {
         );
         // Add Line to log
         connect!(connection_control@connection::Msg::ReciveLine(ref text), logging, log::Msg::LogLine(text.clone()));
+        connect!(connection_control@connection::Msg::ConnectionFailed(ref error), logging, log::Msg::LogLine(error.clone()));
         // Add Line to Command Queue
-        connect!(logging@log::Msg::SendCommand(ref text), relm, Msg::EnqueueCommand(text.clone()));
+        connect!(logging@log::Msg::SendCommand(ref line), relm, Msg::EnqueueCommand(line.clone()));
         // Add Command from control
-        connect!(manual_control@control::Msg::SendCmd(ref text), relm, Msg::EnqueueCommand(text.clone()));
+        connect!(manual_control@control::Msg::SendCmd(ref line), relm, Msg::EnqueueCommand(line.clone()));
+        connect!(manual_control@control::Msg::EmergencyStop, relm, Msg::EmergencyStop);
         // Clear Command Buffer
         connect!(connection_control@connection::Msg::Disconnect, relm, Msg::Disconnect);
         connect!(connection_control@connection::Msg::ConnectionActive, relm, Msg::Connect);
+        connect!(connection_control@connection::Msg::BaudRateDetected(baud_rate), relm, Msg::BaudRateDetected(*baud_rate));
+        // Print jobs
+        connect!(printing@print::Msg::Load(ref path), relm, Msg::LoadJob(path.clone()));
+        connect!(printing@print::Msg::Start, relm, Msg::StartJob);
+        connect!(printing@print::Msg::Pause, relm, Msg::PauseJob);
+        connect!(printing@print::Msg::Resume, relm, Msg::ResumeJob);
+        connect!(printing@print::Msg::Cancel, relm, Msg::CancelJob);
+        connect!(printing@print::Msg::SetParkOnPause(park), relm, Msg::SetParkOnPause(*park));
+        connect!(connection_control@connection::Msg::SetLineNumbers(enabled), relm, Msg::SetLineNumbers(*enabled));
+        connect!(connection_control@connection::Msg::SetStreamingMode(mode), relm, Msg::SetStreamingMode(*mode));
+        connect!(connection_control@connection::Msg::Connecting(ref port, baud_rate, ref serial_number), relm, Msg::Connecting(port.clone(), *baud_rate, serial_number.clone()));
+        connect!(connection_control@connection::Msg::SelectProfile(ref name), relm, Msg::SelectProfile(name.clone()));
+        connect!(settings@settings::Msg::SetConfig(ref config), relm, Msg::SetConfig(config.clone()));
+        printing.emit(print::Msg::SetBuildVolume(
+            model.config.profile().build_volume,
+        ));
         // Connect Response Eval
         connect!(connection_control@connection::Msg::ReciveLine(ref text), relm, Msg::EvalResponse(text.clone()));
 
@@ -198,12 +731,38 @@ impl Widget for Win {
             _manual_control: manual_control,
             _connection_control: connection_control,
             _logging: logging,
+            _printing: printing,
+            _settings: settings,
             _port: None,
+            header_bar,
             model,
         }
     }
 }
 
+/// The scripts of a profile have one command per line
+fn set_scripts(options: &mut job::JobOptions, profile: &config::Profile) {
+    let lines = |script: &str| script.lines().map(|line| line.to_string()).collect();
+    options.start_script = lines(&profile.start_script);
+    options.end_script = lines(&profile.end_script);
+    options.cancel_script = lines(&profile.cancel_script);
+}
+
+/// The filament of a profile, used for the estimate
+fn filament(profile: &config::Profile) -> estimate::Filament {
+    estimate::Filament {
+        diameter: profile.filament_diameter,
+        density: profile.filament_density,
+    }
+}
+
+/// Follow the dark theme preference
+fn apply_theme(ui: &config::UiConfig) {
+    if let Some(settings) = gtk::Settings::get_default() {
+        settings.set_property_gtk_application_prefer_dark_theme(ui.dark_theme);
+    }
+}
+
 fn create_tab_widget(label: &str) -> gtk::Box {
     let tab_widget = gtk::Box::new(gtk::Orientation::Vertical, 0);
     let mut image_path = "resources/png/".to_string();
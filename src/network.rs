@@ -0,0 +1,344 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::transport::{not_open, Transport};
+
+/// Raw TCP, e.g. `tcp://192.168.1.20:8888` for ESP3D
+pub const TCP_PREFIX: &str = "tcp://";
+/// Telnet with the serial port options of RFC 2217, e.g. `rfc2217://octopi.local:2217` for ser2net
+pub const RFC2217_PREFIX: &str = "rfc2217://";
+
+/// How long to wait for a bridge that does not answer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Idle time before the connection is probed, a WiFi bridge that lost power never closes it
+const KEEPALIVE_TIME: Duration = Duration::from_secs(10);
+/// Interval between the probes of an idle connection
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+/// True for the targets reached over the network instead of a local port
+pub fn is_network(target: &str) -> bool {
+    target.starts_with(TCP_PREFIX) || target.starts_with(RFC2217_PREFIX)
+}
+
+/// Settings of the serial port of an RFC 2217 server, coded as in the RFC.
+/// Zero keeps the setting of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ComPort {
+    pub baud_rate: u32,
+    /// 5 to 8
+    pub data_size: u8,
+    /// 1 none, 2 odd, 3 even
+    pub parity: u8,
+    /// 1 one, 2 two
+    pub stop_size: u8,
+    /// 1 none, 2 software, 3 hardware
+    pub control: u8,
+}
+
+pub struct TcpTransport {
+    target: String,
+    /// `host:port`
+    address: String,
+    timeout: Duration,
+    /// Only used by RFC 2217
+    com_port: Option<ComPort>,
+    telnet: Telnet,
+    stream: Option<TcpStream>,
+}
+
+impl TcpTransport {
+    /// The com port settings are only sent to `rfc2217://` targets
+    pub fn new(target: &str, timeout: Duration, com_port: ComPort) -> Self {
+        let (address, com_port) = if let Some(address) = target.strip_prefix(RFC2217_PREFIX) {
+            (address, Some(com_port))
+        } else {
+            (target.trim_start_matches(TCP_PREFIX), None)
+        };
+        TcpTransport {
+            target: target.to_string(),
+            address: address.to_string(),
+            // A zero timeout is not allowed for sockets
+            timeout: timeout.max(Duration::from_millis(1)),
+            com_port,
+            telnet: Telnet::default(),
+            stream: None,
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, String> {
+        let addresses = self
+            .address
+            .to_socket_addrs()
+            .map_err(|err| format!("{}: {}", self.target, err))?;
+        let mut error = format!("{}: Unknown host", self.target);
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => error = format!("{}: {}", self.target, err),
+            }
+        }
+        Err(error)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn open(&mut self) -> Result<(), String> {
+        let mut stream = self.connect()?;
+        let setup = |stream: &mut TcpStream| -> std::io::Result<()> {
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            let keepalive = socket2::TcpKeepalive::new()
+                .with_time(KEEPALIVE_TIME)
+                .with_interval(KEEPALIVE_INTERVAL);
+            socket2::SockRef::from(&*stream).set_tcp_keepalive(&keepalive)?;
+            if let Some(com_port) = self.com_port {
+                stream.write_all(&negotiation(com_port))?;
+            }
+            Ok(())
+        };
+        setup(&mut stream).map_err(|err| format!("{}: {}", self.target, err))?;
+        self.telnet = Telnet::default();
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let stream = self.stream.as_mut().ok_or_else(not_open)?;
+        let n = match stream.read(buffer) {
+            // The bridge closed the connection, e.g. because the printer was turned off
+            Ok(0) if !buffer.is_empty() => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "The connection was closed",
+                ))
+            }
+            Ok(n) => n,
+            // Sockets time out with WouldBlock on Unix, serial ports with TimedOut
+            Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                return Err(std::io::ErrorKind::TimedOut.into())
+            }
+            Err(err) => return Err(err),
+        };
+        if self.com_port.is_none() {
+            return Ok(n);
+        }
+        let (data, answer) = self.telnet.decode(&buffer[..n]);
+        if !answer.is_empty() {
+            stream.write_all(&answer)?;
+        }
+        buffer[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let stream = self.stream.as_mut().ok_or_else(not_open)?;
+        if self.com_port.is_some() {
+            stream.write_all(&escape(data))
+        } else {
+            stream.write_all(data)
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            stream.shutdown(std::net::Shutdown::Both).ok();
+        }
+    }
+
+    fn describe(&self) -> String {
+        self.target.clone()
+    }
+}
+
+/// Binary mode both ways and the serial port settings
+fn negotiation(com_port: ComPort) -> Vec<u8> {
+    let mut bytes = vec![
+        IAC,
+        WILL,
+        BINARY,
+        IAC,
+        DO,
+        BINARY,
+        IAC,
+        WILL,
+        SUPPRESS_GO_AHEAD,
+        IAC,
+        DO,
+        SUPPRESS_GO_AHEAD,
+        IAC,
+        WILL,
+        COM_PORT_OPTION,
+    ];
+    // SET-BAUDRATE, SET-DATASIZE, SET-PARITY, SET-STOPSIZE and SET-CONTROL
+    let options = [
+        (1, com_port.baud_rate.to_be_bytes().to_vec()),
+        (2, vec![com_port.data_size]),
+        (3, vec![com_port.parity]),
+        (4, vec![com_port.stop_size]),
+        (5, vec![com_port.control]),
+    ];
+    for (command, value) in options.iter() {
+        if value.iter().all(|&byte| byte == 0) {
+            continue;
+        }
+        bytes.extend_from_slice(&[IAC, SB, COM_PORT_OPTION, *command]);
+        bytes.extend(escape(value));
+        bytes.extend_from_slice(&[IAC, SE]);
+    }
+    bytes
+}
+
+/// Double every IAC so it is not taken as a command
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+        escaped.push(byte);
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TelnetState {
+    Data,
+    Command,
+    /// WILL, WONT, DO or DONT before the option
+    Option(u8),
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+/// Splits the telnet commands from the data, a command may span two reads
+#[derive(Debug)]
+struct Telnet {
+    state: TelnetState,
+}
+
+impl Default for Telnet {
+    fn default() -> Self {
+        Telnet {
+            state: TelnetState::Data,
+        }
+    }
+}
+
+impl Telnet {
+    /// The data and the answer to the commands of the server
+    fn decode(&mut self, input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut data = Vec::with_capacity(input.len());
+        let mut answer = Vec::new();
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (TelnetState::Data, IAC) => TelnetState::Command,
+                (TelnetState::Data, _) => {
+                    data.push(byte);
+                    TelnetState::Data
+                }
+                (TelnetState::Command, IAC) => {
+                    data.push(IAC);
+                    TelnetState::Data
+                }
+                (TelnetState::Command, WILL..=DONT) => TelnetState::Option(byte),
+                (TelnetState::Command, SB) => TelnetState::Subnegotiation,
+                // NOP, go ahead and the like
+                (TelnetState::Command, _) => TelnetState::Data,
+                (TelnetState::Option(command), option) => {
+                    // The supported options were offered when connecting,
+                    // answering them again could start a loop
+                    let supported = [BINARY, SUPPRESS_GO_AHEAD, COM_PORT_OPTION].contains(&option);
+                    match command {
+                        DO if !supported => answer.extend_from_slice(&[IAC, WONT, option]),
+                        WILL if !supported => answer.extend_from_slice(&[IAC, DONT, option]),
+                        _ => (),
+                    }
+                    TelnetState::Data
+                }
+                // The server confirms the serial port settings, nothing to do about it
+                (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationCommand,
+                (TelnetState::Subnegotiation, _) => TelnetState::Subnegotiation,
+                (TelnetState::SubnegotiationCommand, SE) => TelnetState::Data,
+                (TelnetState::SubnegotiationCommand, _) => TelnetState::Subnegotiation,
+            };
+        }
+        (data, answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telnet_commands() {
+        let mut telnet = Telnet::default();
+        // An escaped IAC, a request for echo and a confirmed baud rate split over two reads
+        let (data, answer) = telnet.decode(b"ok T:20\xff\xff\xff\xfd\x01\xff\xfa\x2c\x65\x00");
+        assert_eq!(data, b"ok T:20\xff");
+        assert_eq!(answer, vec![IAC, WONT, 1]);
+        let (data, answer) = telnet.decode(b"\x01\xc2\x00\xff\xf0\xff\xfb\x00\n");
+        assert_eq!(data, b"\n");
+        assert!(answer.is_empty());
+    }
+
+    #[test]
+    fn com_port_options() {
+        let bytes = negotiation(ComPort {
+            baud_rate: 115_200,
+            data_size: 8,
+            parity: 1,
+            ..ComPort::default()
+        });
+        assert!(bytes.ends_with(&[
+            IAC, SB, 44, 1, 0, 1, 0xc2, 0, IAC, SE, IAC, SB, 44, 2, 8, IAC, SE, IAC, SB, 44, 3, 1,
+            IAC, SE
+        ]));
+        assert_eq!(escape(&[1, IAC, 2]), vec![1, IAC, IAC, 2]);
+    }
+
+    #[test]
+    fn tcp_round_trip() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 64];
+            let n = stream.read(&mut buffer).unwrap();
+            assert_eq!(&buffer[..n], b"M105\n");
+            stream.write_all(b"ok T:21.0 /0.0\n").unwrap();
+        });
+
+        let target = format!("{}{}", TCP_PREFIX, address);
+        let mut transport =
+            TcpTransport::new(&target, Duration::from_millis(10), ComPort::default());
+        transport.open().unwrap();
+        transport.write(b"M105\n").unwrap();
+        let mut received = Vec::new();
+        let mut buffer = [0; 64];
+        // The server hangs up after the answer
+        let error = loop {
+            match transport.read(&mut buffer) {
+                Ok(n) => received.extend_from_slice(&buffer[..n]),
+                Err(ref err) if err.kind() == std::io::ErrorKind::TimedOut => (),
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(received, b"ok T:21.0 /0.0\n");
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        server.join().unwrap();
+        transport.close();
+    }
+}
@@ -0,0 +1,718 @@
+use gtk::prelude::*;
+use relm::{connect, Relm};
+use relm_derive::Msg;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::toolpath;
+
+/// Space around the part in pixels
+const MARGIN: f64 = 20.0;
+/// Extrusion width drawn in mm
+const EXTRUSION_WIDTH: f64 = 0.4;
+/// Size of the build volume in mm shown in the 3D view until a printer profile sets it
+const DEFAULT_BUILD_VOLUME: [f32; 3] = [220.0, 220.0, 250.0];
+/// Rotation of the isometric view around Z and the elevation
+const ISOMETRIC_ANGLES: (f64, f64) = (-std::f64::consts::FRAC_PI_4, 0.615_479_708_670_387);
+
+#[derive(Msg)]
+pub enum Msg {
+    SetToolpath(Rc<toolpath::Toolpath>),
+    LayerChanged,
+    ShowTravel(bool),
+    /// Show all layers up to the current one in 3D
+    ShowIsometric(bool),
+    Follow(bool),
+    ResetProgress,
+    LineSent(usize),
+    LineAcknowledged(usize),
+    SetHeadPosition([f32; 3]),
+    SetBuildVolume([f32; 3]),
+    Zoom((f64, f64), f64),
+    DragStart((f64, f64)),
+    Drag((f64, f64)),
+    ResetView,
+}
+
+/// Lines of the running job that were sent and acknowledged
+#[derive(Debug, Clone, Copy, Default)]
+struct JobProgress {
+    sent: usize,
+    acknowledged: usize,
+}
+
+/// How a move is drawn depending on the progress of the job
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    /// Printed, or no job is running
+    Normal,
+    /// Sent but not acknowledged yet
+    Sent,
+    Pending,
+}
+
+/// What is drawn, shared with the draw handler
+struct View {
+    toolpath: Rc<toolpath::Toolpath>,
+    layer: usize,
+    show_travel: bool,
+    progress: Option<JobProgress>,
+    /// Nozzle position as reported by `M114`
+    head: Option<[f32; 3]>,
+    isometric: bool,
+    build_volume: [f32; 3],
+    /// Rotation around Z and elevation of the 3D view in radians
+    angles: (f64, f64),
+    zoom: f64,
+    /// Offset of the drawing in pixels
+    pan: (f64, f64),
+}
+
+pub struct Model {
+    view: Rc<RefCell<View>>,
+    /// Pointer position when dragging started
+    drag_start: (f64, f64),
+    /// Pan or angles of the 3D view when dragging started
+    drag_from: (f64, f64),
+    /// Show the layer being printed
+    follow: bool,
+}
+
+struct GtkWidgets {
+    root: gtk::Box,
+    drawing_area: gtk::DrawingArea,
+    layer_scale: gtk::Scale,
+    label_layer: gtk::Label,
+}
+
+pub struct Widget {
+    model: Model,
+    widgets: GtkWidgets,
+}
+
+impl relm::Update for Widget {
+    type Model = Model;
+    type ModelParam = ();
+    type Msg = Msg;
+
+    fn model(_relm: &Relm<Self>, _param: Self::ModelParam) -> Self::Model {
+        Model {
+            view: Rc::new(RefCell::new(View {
+                toolpath: Rc::new(toolpath::Toolpath::default()),
+                layer: 0,
+                show_travel: false,
+                progress: None,
+                head: None,
+                isometric: false,
+                build_volume: DEFAULT_BUILD_VOLUME,
+                angles: ISOMETRIC_ANGLES,
+                zoom: 1.0,
+                pan: (0.0, 0.0),
+            })),
+            drag_start: (0.0, 0.0),
+            drag_from: (0.0, 0.0),
+            follow: true,
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            Msg::SetToolpath(toolpath) => {
+                let layers = toolpath.layers.len();
+                {
+                    let mut view = self.model.view.borrow_mut();
+                    view.toolpath = toolpath;
+                    view.progress = None;
+                }
+                self.widgets
+                    .layer_scale
+                    .set_range(0.0, layers.saturating_sub(1) as f64);
+                self.widgets.layer_scale.set_value(0.0);
+                self.update(Msg::LayerChanged);
+                self.update(Msg::ResetView);
+            }
+            Msg::LayerChanged => {
+                let layer = self.widgets.layer_scale.get_value().round() as usize;
+                let mut view = self.model.view.borrow_mut();
+                view.layer = layer;
+                self.widgets
+                    .label_layer
+                    .set_text(&match view.toolpath.layers.get(layer) {
+                        Some(current) => format!(
+                            "Layer {} / {} at Z{:.2}",
+                            layer + 1,
+                            view.toolpath.layers.len(),
+                            current.z
+                        ),
+                        None => "No layers".to_string(),
+                    });
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::ShowTravel(show) => {
+                self.model.view.borrow_mut().show_travel = show;
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::ShowIsometric(isometric) => {
+                self.model.view.borrow_mut().isometric = isometric;
+                self.update(Msg::ResetView);
+            }
+            Msg::Follow(follow) => self.model.follow = follow,
+            Msg::ResetProgress => {
+                self.model.view.borrow_mut().progress = Some(JobProgress::default());
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::LineSent(line) => {
+                if let Some(ref mut progress) = self.model.view.borrow_mut().progress {
+                    progress.sent = progress.sent.max(line + 1);
+                }
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::LineAcknowledged(line) => {
+                let layer = {
+                    let mut view = self.model.view.borrow_mut();
+                    if let Some(ref mut progress) = view.progress {
+                        progress.acknowledged = progress.acknowledged.max(line + 1);
+                    }
+                    view.toolpath.layer_of_line(line)
+                };
+                match layer {
+                    // Changing the layer redraws
+                    Some(layer) if self.model.follow && layer != self.model.view.borrow().layer => {
+                        self.widgets.layer_scale.set_value(layer as f64)
+                    }
+                    _ => self.widgets.drawing_area.queue_draw(),
+                }
+            }
+            Msg::SetHeadPosition(position) => {
+                self.model.view.borrow_mut().head = Some(position);
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::SetBuildVolume(build_volume) => {
+                self.model.view.borrow_mut().build_volume = build_volume;
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::Zoom((x, y), factor) => {
+                let mut view = self.model.view.borrow_mut();
+                let new_zoom = (view.zoom * factor).max(0.5).min(100.0);
+                let factor = new_zoom / view.zoom;
+                // Keep the point under the pointer in place
+                let offset_x = x
+                    - f64::from(self.widgets.drawing_area.get_allocated_width()) / 2.0
+                    - view.pan.0;
+                let offset_y = y
+                    - f64::from(self.widgets.drawing_area.get_allocated_height()) / 2.0
+                    - view.pan.1;
+                view.pan.0 += offset_x * (1.0 - factor);
+                view.pan.1 += offset_y * (1.0 - factor);
+                view.zoom = new_zoom;
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::DragStart(position) => {
+                let view = self.model.view.borrow();
+                self.model.drag_start = position;
+                self.model.drag_from = if view.isometric {
+                    view.angles
+                } else {
+                    view.pan
+                };
+            }
+            Msg::Drag((x, y)) => {
+                let mut view = self.model.view.borrow_mut();
+                let (dx, dy) = (x - self.model.drag_start.0, y - self.model.drag_start.1);
+                let (from_x, from_y) = self.model.drag_from;
+                // Dragging rotates the 3D view and moves the layer view
+                if view.isometric {
+                    view.angles = (
+                        from_x + dx * 0.01,
+                        (from_y + dy * 0.01)
+                            .max(0.0)
+                            .min(std::f64::consts::FRAC_PI_2),
+                    );
+                } else {
+                    view.pan = (from_x + dx, from_y + dy);
+                }
+                self.widgets.drawing_area.queue_draw();
+            }
+            Msg::ResetView => {
+                let mut view = self.model.view.borrow_mut();
+                view.zoom = 1.0;
+                view.pan = (0.0, 0.0);
+                view.angles = ISOMETRIC_ANGLES;
+                self.widgets.drawing_area.queue_draw();
+            }
+        }
+    }
+}
+
+impl relm::Widget for Widget {
+    type Root = gtk::Box;
+
+    fn root(&self) -> Self::Root {
+        self.widgets.root.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        // The root widget
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 3);
+
+        // The drawing with the layer slider next to it
+        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+
+        let drawing_area = gtk::DrawingArea::new();
+        drawing_area.set_size_request(300, 300);
+        drawing_area.add_events(
+            gdk::EventMask::SCROLL_MASK
+                | gdk::EventMask::BUTTON_PRESS_MASK
+                | gdk::EventMask::BUTTON1_MOTION_MASK,
+        );
+        let view = model.view.clone();
+        drawing_area.connect_draw(move |widget, cr| {
+            draw(
+                &view.borrow(),
+                f64::from(widget.get_allocated_width()),
+                f64::from(widget.get_allocated_height()),
+                cr,
+            );
+            gtk::Inhibit(false)
+        });
+        hbox.pack_start(&drawing_area, true, true, 0);
+
+        // Top layer at the top
+        let layer_scale = gtk::Scale::with_range(gtk::Orientation::Vertical, 0.0, 1.0, 1.0);
+        layer_scale.set_inverted(true);
+        layer_scale.set_digits(0);
+        layer_scale.set_draw_value(false);
+        hbox.pack_start(&layer_scale, false, false, 0);
+
+        vbox.pack_start(&hbox, true, true, 0);
+
+        // Information and view options below the drawing
+        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+
+        let label_layer = gtk::Label::new(Some("No layers"));
+        label_layer.set_halign(gtk::Align::Start);
+        hbox.pack_start(&label_layer, true, true, 3);
+
+        let travel_btn = gtk::CheckButton::with_label("Travel moves");
+        hbox.pack_start(&travel_btn, false, false, 3);
+
+        let isometric_btn = gtk::ToggleButton::with_label("3D");
+        hbox.pack_start(&isometric_btn, false, false, 3);
+
+        let follow_btn = gtk::CheckButton::with_label("Follow print");
+        follow_btn.set_active(true);
+        hbox.pack_start(&follow_btn, false, false, 3);
+
+        let fit_btn = gtk::Button::with_label("Fit");
+        hbox.pack_start(&fit_btn, false, false, 3);
+
+        vbox.pack_start(&hbox, false, false, 0);
+
+        connect!(
+            relm,
+            layer_scale,
+            connect_value_changed(_),
+            Msg::LayerChanged
+        );
+        connect!(
+            relm,
+            travel_btn,
+            connect_toggled(btn),
+            Msg::ShowTravel(btn.get_active())
+        );
+        connect!(
+            relm,
+            isometric_btn,
+            connect_toggled(btn),
+            Msg::ShowIsometric(btn.get_active())
+        );
+        connect!(
+            relm,
+            follow_btn,
+            connect_toggled(btn),
+            Msg::Follow(btn.get_active())
+        );
+        connect!(relm, fit_btn, connect_clicked(_), Msg::ResetView);
+        connect!(
+            relm,
+            drawing_area,
+            connect_scroll_event(_, event),
+            return (
+                Some(Msg::Zoom(event.get_position(), zoom_factor(event))),
+                gtk::Inhibit(true)
+            )
+        );
+        connect!(
+            relm,
+            drawing_area,
+            connect_button_press_event(_, event),
+            return (
+                Some(Msg::DragStart(event.get_position())),
+                gtk::Inhibit(false)
+            )
+        );
+        connect!(
+            relm,
+            drawing_area,
+            connect_motion_notify_event(_, event),
+            return (Some(Msg::Drag(event.get_position())), gtk::Inhibit(false))
+        );
+
+        Self {
+            model,
+            widgets: GtkWidgets {
+                root: vbox,
+                drawing_area,
+                layer_scale,
+                label_layer,
+            },
+        }
+    }
+}
+
+fn zoom_factor(event: &gdk::EventScroll) -> f64 {
+    match event.get_direction() {
+        gdk::ScrollDirection::Up => 1.25,
+        gdk::ScrollDirection::Down => 0.8,
+        _ => 1.0,
+    }
+}
+
+/// Maps printer coordinates to pixels, the Y axis pointing up
+struct Transform {
+    scale: f64,
+    offset: (f64, f64),
+}
+
+impl Transform {
+    /// Fit the part into the drawing area, then zoom and pan
+    fn new(view: &View, bounding_box: &toolpath::BoundingBox, width: f64, height: f64) -> Self {
+        let size = bounding_box.size();
+        let fit = ((width - 2.0 * MARGIN) / f64::from(size[0].max(1.0)))
+            .min((height - 2.0 * MARGIN) / f64::from(size[1].max(1.0)));
+        let scale = fit.max(0.01) * view.zoom;
+        let center = (
+            f64::from(bounding_box.min[0] + bounding_box.max[0]) / 2.0,
+            f64::from(bounding_box.min[1] + bounding_box.max[1]) / 2.0,
+        );
+        Transform {
+            scale,
+            offset: (
+                width / 2.0 + view.pan.0 - center.0 * scale,
+                height / 2.0 + view.pan.1 + center.1 * scale,
+            ),
+        }
+    }
+
+    fn point(&self, point: &[f32; 3]) -> (f64, f64) {
+        (
+            self.offset.0 + f64::from(point[0]) * self.scale,
+            self.offset.1 - f64::from(point[1]) * self.scale,
+        )
+    }
+}
+
+/// Orthographic projection of the printer coordinates, seen from the front and above
+struct Projection {
+    build_volume: [f32; 3],
+    /// Sine and cosine of the rotation around Z
+    yaw: (f64, f64),
+    /// Sine and cosine of the elevation
+    pitch: (f64, f64),
+    scale: f64,
+    offset: (f64, f64),
+}
+
+impl Projection {
+    /// Fit the build volume into the drawing area, then zoom and pan
+    fn new(view: &View, width: f64, height: f64) -> Self {
+        let mut projection = Projection {
+            build_volume: view.build_volume,
+            yaw: view.angles.0.sin_cos(),
+            pitch: view.angles.1.sin_cos(),
+            scale: 1.0,
+            offset: (0.0, 0.0),
+        };
+
+        let mut min = (std::f64::MAX, std::f64::MAX);
+        let mut max = (std::f64::MIN, std::f64::MIN);
+        for index in 0..8 {
+            let mut point = [0.0; 3];
+            for (axis, value) in point.iter_mut().enumerate() {
+                if index & (1 << axis) != 0 {
+                    *value = view.build_volume[axis];
+                }
+            }
+            let (x, y, _) = projection.point(&point);
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+
+        let fit = ((width - 2.0 * MARGIN) / (max.0 - min.0).max(1.0))
+            .min((height - 2.0 * MARGIN) / (max.1 - min.1).max(1.0));
+        projection.scale = fit.max(0.01) * view.zoom;
+        projection.offset = (
+            width / 2.0 + view.pan.0 - (min.0 + max.0) / 2.0 * projection.scale,
+            height / 2.0 + view.pan.1 - (min.1 + max.1) / 2.0 * projection.scale,
+        );
+        projection
+    }
+
+    /// Position in pixels and the depth in mm, larger is further away
+    fn point(&self, point: &[f32; 3]) -> (f64, f64, f64) {
+        // Rotate around the center of the build volume
+        let x = f64::from(point[0] - self.build_volume[0] / 2.0);
+        let y = f64::from(point[1] - self.build_volume[1] / 2.0);
+        let z = f64::from(point[2] - self.build_volume[2] / 2.0);
+        let (yaw_sin, yaw_cos) = self.yaw;
+        let (pitch_sin, pitch_cos) = self.pitch;
+        let rotated_x = x * yaw_cos - y * yaw_sin;
+        let rotated_y = x * yaw_sin + y * yaw_cos;
+        let up = z * pitch_cos + rotated_y * pitch_sin;
+        let depth = rotated_y * pitch_cos - z * pitch_sin;
+        (
+            self.offset.0 + rotated_x * self.scale,
+            self.offset.1 - up * self.scale,
+            depth,
+        )
+    }
+}
+
+fn feature_color(feature: toolpath::Feature) -> (f64, f64, f64) {
+    match feature {
+        toolpath::Feature::Unknown => (0.9, 0.6, 0.2),
+        toolpath::Feature::OuterWall => (1.0, 0.5, 0.0),
+        toolpath::Feature::InnerWall => (1.0, 0.85, 0.3),
+        toolpath::Feature::Infill => (0.7, 0.2, 0.2),
+        toolpath::Feature::SolidInfill => (0.6, 0.3, 0.8),
+        toolpath::Feature::Bridge => (0.3, 0.6, 0.9),
+        toolpath::Feature::Support => (0.3, 0.8, 0.3),
+        toolpath::Feature::Skirt => (0.5, 0.8, 0.8),
+        toolpath::Feature::Custom => (0.6, 0.6, 0.6),
+    }
+}
+
+fn draw(view: &View, width: f64, height: f64, cr: &cairo::Context) {
+    cr.set_source_rgb(0.15, 0.15, 0.15);
+    cr.paint();
+    cr.set_line_cap(cairo::LineCap::Round);
+    cr.set_line_join(cairo::LineJoin::Round);
+
+    if view.isometric {
+        draw_isometric(view, width, height, cr);
+    } else {
+        draw_layer(view, width, height, cr);
+    }
+}
+
+fn style(view: &View, segment: &toolpath::Segment) -> Style {
+    match view.progress {
+        Some(progress) if segment.line >= progress.sent => Style::Pending,
+        Some(progress) if segment.line >= progress.acknowledged => Style::Sent,
+        _ => Style::Normal,
+    }
+}
+
+/// Set the color of an extrusion, brightness from 0.0 to 1.0
+fn set_extrusion_color(
+    cr: &cairo::Context,
+    feature: toolpath::Feature,
+    style: Style,
+    brightness: f64,
+) {
+    let (red, green, blue) = feature_color(feature);
+    match style {
+        Style::Normal => cr.set_source_rgb(red * brightness, green * brightness, blue * brightness),
+        Style::Sent => cr.set_source_rgb(brightness, brightness, brightness),
+        Style::Pending => cr.set_source_rgba(
+            red * brightness,
+            green * brightness,
+            blue * brightness,
+            0.25,
+        ),
+    }
+}
+
+/// The nozzle as a crosshair
+fn draw_head(cr: &cairo::Context, x: f64, y: f64) {
+    cr.set_source_rgb(1.0, 1.0, 1.0);
+    cr.set_line_width(2.0);
+    cr.new_sub_path();
+    cr.arc(x, y, 6.0, 0.0, 2.0 * std::f64::consts::PI);
+    cr.move_to(x - 12.0, y);
+    cr.line_to(x + 12.0, y);
+    cr.move_to(x, y - 12.0);
+    cr.line_to(x, y + 12.0);
+    cr.stroke();
+}
+
+/// Draw the current layer with the layer below it for orientation
+fn draw_layer(view: &View, width: f64, height: f64, cr: &cairo::Context) {
+    let toolpath = &view.toolpath;
+    let (bounding_box, layer) = match (toolpath.bounding_box, toolpath.layers.get(view.layer)) {
+        (Some(bounding_box), Some(layer)) => (bounding_box, layer),
+        _ => return,
+    };
+    let transform = Transform::new(view, &bounding_box, width, height);
+
+    let line = |segment: &toolpath::Segment| {
+        let (x, y) = transform.point(&segment.from);
+        cr.move_to(x, y);
+        let (x, y) = transform.point(&segment.to);
+        cr.line_to(x, y);
+    };
+
+    if let Some(below) = view
+        .layer
+        .checked_sub(1)
+        .and_then(|index| toolpath.layers.get(index))
+    {
+        cr.set_source_rgba(1.0, 1.0, 1.0, 0.15);
+        cr.set_line_width(1.0);
+        for segment in toolpath.segments[below.segments.clone()].iter() {
+            if segment.kind == toolpath::MoveKind::Extrude {
+                line(segment);
+            }
+        }
+        cr.stroke();
+    }
+
+    let segments = &toolpath.segments[layer.segments.clone()];
+
+    // Consecutive moves of the same feature are stroked at once
+    cr.set_line_width((EXTRUSION_WIDTH * transform.scale).max(1.0));
+    let mut current = None;
+    for segment in segments.iter() {
+        if segment.kind != toolpath::MoveKind::Extrude {
+            continue;
+        }
+        let next = (segment.feature, style(view, segment));
+        if current != Some(next) {
+            cr.stroke();
+            set_extrusion_color(cr, next.0, next.1, 1.0);
+            current = Some(next);
+        }
+        line(segment);
+    }
+    cr.stroke();
+
+    if view.show_travel {
+        cr.set_source_rgba(0.3, 0.5, 1.0, 0.6);
+        cr.set_line_width(1.0);
+        cr.set_dash(&[4.0, 4.0], 0.0);
+        for segment in segments.iter() {
+            if segment.kind == toolpath::MoveKind::Travel {
+                line(segment);
+            }
+        }
+        cr.stroke();
+        cr.set_dash(&[], 0.0);
+    }
+
+    // Retractions as red and unretractions as green dots
+    for (kind, (red, green, blue)) in [
+        (toolpath::MoveKind::Retract, (0.9, 0.1, 0.1)),
+        (toolpath::MoveKind::Unretract, (0.1, 0.8, 0.1)),
+    ]
+    .iter()
+    {
+        cr.set_source_rgb(*red, *green, *blue);
+        for segment in segments.iter() {
+            if segment.kind == *kind {
+                let (x, y) = transform.point(&segment.to);
+                cr.new_sub_path();
+                cr.arc(x, y, 3.0, 0.0, 2.0 * std::f64::consts::PI);
+            }
+        }
+        cr.fill();
+    }
+
+    if let Some(head) = view.head {
+        let (x, y) = transform.point(&head);
+        draw_head(cr, x, y);
+    }
+}
+
+/// Draw all layers up to the current one inside the build volume
+fn draw_isometric(view: &View, width: f64, height: f64, cr: &cairo::Context) {
+    let projection = Projection::new(view, width, height);
+    let corner = |index: usize| {
+        let mut point = [0.0; 3];
+        for (axis, value) in point.iter_mut().enumerate() {
+            if index & (1 << axis) != 0 {
+                *value = view.build_volume[axis];
+            }
+        }
+        let (x, y, _) = projection.point(&point);
+        (x, y)
+    };
+
+    // The bed
+    cr.set_source_rgba(1.0, 1.0, 1.0, 0.08);
+    for (index, corner_index) in [0, 1, 3, 2].iter().enumerate() {
+        let (x, y) = corner(*corner_index);
+        if index == 0 {
+            cr.move_to(x, y);
+        } else {
+            cr.line_to(x, y);
+        }
+    }
+    cr.close_path();
+    cr.fill();
+
+    // The edges of the build volume connect corners that differ in one axis
+    cr.set_source_rgba(1.0, 1.0, 1.0, 0.3);
+    cr.set_line_width(1.0);
+    for index in 0..8 {
+        for axis in 0..3 {
+            if index & (1 << axis) == 0 {
+                let (x, y) = corner(index);
+                cr.move_to(x, y);
+                let (x, y) = corner(index | (1 << axis));
+                cr.line_to(x, y);
+            }
+        }
+    }
+    cr.stroke();
+
+    let toolpath = &view.toolpath;
+    let end = toolpath
+        .layers
+        .get(view.layer)
+        .map_or(0, |layer| layer.segments.end);
+    let radius = f64::from(
+        view.build_volume
+            .iter()
+            .map(|size| size * size)
+            .sum::<f32>()
+            .sqrt(),
+    ) / 2.0;
+
+    // Lower layers are drawn first, so they are covered by the layers above
+    cr.set_line_width((EXTRUSION_WIDTH * projection.scale).max(1.0));
+    let mut current = None;
+    for segment in toolpath.segments[..end].iter() {
+        if segment.kind != toolpath::MoveKind::Extrude {
+            continue;
+        }
+        let (from_x, from_y, from_depth) = projection.point(&segment.from);
+        let (to_x, to_y, to_depth) = projection.point(&segment.to);
+        // Closer moves are brighter, in a few steps so that moves can still be stroked together
+        let depth = ((from_depth + to_depth) / 2.0 / radius).max(-1.0).min(1.0);
+        let shade = (8.0 - (depth + 1.0) * 3.0).round() as u8;
+        let next = (segment.feature, style(view, segment), shade);
+        if current != Some(next) {
+            cr.stroke();
+            set_extrusion_color(cr, next.0, next.1, f64::from(shade) / 8.0);
+            current = Some(next);
+        }
+        cr.move_to(from_x, from_y);
+        cr.line_to(to_x, to_y);
+    }
+    cr.stroke();
+
+    if let Some(head) = view.head {
+        let (x, y, _) = projection.point(&head);
+        draw_head(cr, x, y);
+    }
+}
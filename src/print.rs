@@ -0,0 +1,332 @@
+use gtk::prelude::*;
+use relm::{connect, Component, ContainerWidget, Relm};
+use relm_derive::Msg;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::job;
+use crate::preview;
+use crate::toolpath;
+
+#[derive(Msg)]
+pub enum Msg {
+    FileSelected,
+    Load(PathBuf),
+    Start,
+    Pause,
+    Resume,
+    Cancel,
+    SetParkOnPause(bool),
+    SetStatus(job::JobStatus),
+    SetToolpath(Rc<toolpath::Toolpath>),
+    /// A new job started streaming
+    ResetProgress,
+    LineSent(usize),
+    LineAcknowledged(usize),
+    SetHeadPosition([f32; 3]),
+    SetBuildVolume([f32; 3]),
+    SetError(String),
+}
+
+pub struct Model {
+    stream: relm::EventStream<Msg>,
+}
+
+struct GtkWidgets {
+    root: gtk::Box,
+    file_chooser: gtk::FileChooserButton,
+    start_btn: gtk::Button,
+    pause_btn: gtk::Button,
+    resume_btn: gtk::Button,
+    cancel_btn: gtk::Button,
+    label_file: gtk::Label,
+    label_state: gtk::Label,
+    label_line: gtk::Label,
+    label_layer: gtk::Label,
+    label_filament: gtk::Label,
+    label_size: gtk::Label,
+    label_bytes: gtk::Label,
+    label_elapsed: gtk::Label,
+    label_estimated: gtk::Label,
+    label_remaining: gtk::Label,
+    label_progress: gtk::Label,
+    progress_bar: gtk::ProgressBar,
+}
+
+pub struct Widget {
+    model: Model,
+    widgets: GtkWidgets,
+    preview: Component<preview::Widget>,
+}
+
+impl relm::Update for Widget {
+    type Model = Model;
+    type ModelParam = ();
+    type Msg = Msg;
+
+    fn model(relm: &Relm<Self>, _param: Self::ModelParam) -> Self::Model {
+        Model {
+            stream: relm.stream().clone(),
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            Msg::FileSelected => {
+                if let Some(path) = self.widgets.file_chooser.get_filename() {
+                    self.model.stream.emit(Msg::Load(path));
+                }
+            }
+            Msg::Load(_path) => (),
+            Msg::Start => (),
+            Msg::Pause => (),
+            Msg::Resume => (),
+            Msg::Cancel => (),
+            Msg::SetParkOnPause(_park) => (),
+            Msg::SetStatus(status) => {
+                self.widgets.label_file.set_text(&status.name);
+                self.widgets.label_state.set_text(match status.state {
+                    job::JobState::Loaded => "Ready",
+                    job::JobState::Running => "Printing",
+                    job::JobState::Paused => "Paused",
+                    job::JobState::Cancelled => "Cancelled",
+                    job::JobState::Finished => "Finished",
+                });
+                self.widgets
+                    .label_line
+                    .set_text(&format!("{} / {}", status.current_line, status.total_lines));
+                self.widgets.label_layer.set_text(&match status.layer {
+                    Some(ref layer) => format!(
+                        "{} / {} at Z{:.2}, estimated {}",
+                        status.current_layer,
+                        status.total_layers,
+                        layer.z,
+                        format_duration(status.layer_time)
+                    ),
+                    None => format!("{} / {}", status.current_layer, status.total_layers),
+                });
+                self.widgets.label_filament.set_text(&format!(
+                    "{:.2} m, {:.1} g",
+                    status.filament_length / 1000.0,
+                    status.filament_weight
+                ));
+                self.widgets
+                    .label_size
+                    .set_text(&match status.bounding_box {
+                        Some(bounding_box) => {
+                            let size = bounding_box.size();
+                            format!(
+                                "{:.1} x {:.1} x {:.1} mm from X{:.1} Y{:.1} Z{:.1}",
+                                size[0],
+                                size[1],
+                                size[2],
+                                bounding_box.min[0],
+                                bounding_box.min[1],
+                                bounding_box.min[2]
+                            )
+                        }
+                        None => String::new(),
+                    });
+                self.widgets
+                    .label_bytes
+                    .set_text(&format!("{} / {}", status.bytes_sent, status.bytes_total));
+                self.widgets
+                    .label_elapsed
+                    .set_text(&format_duration(status.elapsed));
+                self.widgets
+                    .label_estimated
+                    .set_text(&format_duration(status.estimated_total));
+                self.widgets
+                    .label_remaining
+                    .set_text(&status.remaining.map(format_duration).unwrap_or_default());
+                self.widgets.label_progress.set_text(&format!(
+                    "{:.1}% of bytes, {:.1}% of lines, {:.1}% of time",
+                    status.progress.bytes * 100.0,
+                    status.progress.lines * 100.0,
+                    status.progress.time * 100.0
+                ));
+                self.widgets
+                    .progress_bar
+                    .set_fraction(f64::from(status.progress.time));
+                self.widgets
+                    .progress_bar
+                    .set_text(Some(&format!("{:.0}%", status.progress.time * 100.0)));
+                let active =
+                    status.state == job::JobState::Running || status.state == job::JobState::Paused;
+                self.widgets.start_btn.set_sensitive(!active);
+                self.widgets
+                    .pause_btn
+                    .set_sensitive(status.state == job::JobState::Running);
+                self.widgets
+                    .resume_btn
+                    .set_sensitive(status.state == job::JobState::Paused);
+                self.widgets.cancel_btn.set_sensitive(active);
+            }
+            Msg::SetToolpath(toolpath) => {
+                self.preview.emit(preview::Msg::SetToolpath(toolpath));
+            }
+            Msg::ResetProgress => self.preview.emit(preview::Msg::ResetProgress),
+            Msg::LineSent(line) => self.preview.emit(preview::Msg::LineSent(line)),
+            Msg::LineAcknowledged(line) => self.preview.emit(preview::Msg::LineAcknowledged(line)),
+            Msg::SetHeadPosition(position) => {
+                self.preview.emit(preview::Msg::SetHeadPosition(position))
+            }
+            Msg::SetBuildVolume(build_volume) => self
+                .preview
+                .emit(preview::Msg::SetBuildVolume(build_volume)),
+            Msg::SetError(error) => {
+                self.widgets.label_state.set_text(&error);
+            }
+        }
+    }
+}
+
+impl relm::Widget for Widget {
+    type Root = gtk::Box;
+
+    fn root(&self) -> Self::Root {
+        self.widgets.root.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        // The root widget
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+
+        // Choose a file and start the print
+        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some("G-code"));
+        for pattern in ["*.gcode", "*.gco", "*.g"].iter() {
+            filter.add_pattern(pattern);
+        }
+        let file_chooser = gtk::FileChooserButton::new("Open G-code", gtk::FileChooserAction::Open);
+        file_chooser.add_filter(&filter);
+        hbox.pack_start(&file_chooser, true, true, 0);
+
+        let start_btn = gtk::Button::with_label("Start");
+        start_btn.get_style_context().add_class("suggested-action");
+        start_btn.set_sensitive(false);
+        hbox.pack_start(&start_btn, false, false, 3);
+
+        let pause_btn = gtk::Button::with_label("Pause");
+        pause_btn.set_sensitive(false);
+        hbox.pack_start(&pause_btn, false, false, 3);
+
+        let resume_btn = gtk::Button::with_label("Resume");
+        resume_btn.set_sensitive(false);
+        hbox.pack_start(&resume_btn, false, false, 3);
+
+        let cancel_btn = gtk::Button::with_label("Cancel");
+        cancel_btn
+            .get_style_context()
+            .add_class("destructive-action");
+        cancel_btn.set_sensitive(false);
+        hbox.pack_start(&cancel_btn, false, false, 3);
+
+        vbox.pack_start(&hbox, false, false, 3);
+
+        let park_btn = gtk::CheckButton::with_label("Retract and park the head on pause");
+        park_btn.set_active(true);
+        vbox.pack_start(&park_btn, false, false, 3);
+
+        // A Grid for the job status
+        let grid_status = gtk::Grid::new();
+        grid_status.set_column_spacing(10);
+        grid_status.set_row_spacing(3);
+
+        let label_file = gtk::Label::new(None);
+        let label_state = gtk::Label::new(None);
+        let label_line = gtk::Label::new(None);
+        let label_layer = gtk::Label::new(None);
+        let label_filament = gtk::Label::new(None);
+        let label_size = gtk::Label::new(None);
+        let label_bytes = gtk::Label::new(None);
+        let label_elapsed = gtk::Label::new(None);
+        let label_estimated = gtk::Label::new(None);
+        let label_remaining = gtk::Label::new(None);
+        let label_progress = gtk::Label::new(None);
+
+        for (row, (label, value)) in [
+            ("File:", &label_file),
+            ("State:", &label_state),
+            ("Line:", &label_line),
+            ("Layer:", &label_layer),
+            ("Filament:", &label_filament),
+            ("Size:", &label_size),
+            ("Bytes sent:", &label_bytes),
+            ("Elapsed:", &label_elapsed),
+            ("Estimated:", &label_estimated),
+            ("Remaining:", &label_remaining),
+            ("Progress:", &label_progress),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let row = row as i32;
+            let label = gtk::Label::new(Some(label));
+            label.set_halign(gtk::Align::End);
+            value.set_halign(gtk::Align::Start);
+            grid_status.attach(&label, 0, row, 1, 1);
+            grid_status.attach(*value, 1, row, 1, 1);
+        }
+
+        vbox.pack_start(&grid_status, false, false, 20);
+
+        let progress_bar = gtk::ProgressBar::new();
+        progress_bar.set_show_text(true);
+        vbox.pack_start(&progress_bar, false, false, 3);
+
+        // The toolpath preview takes the remaining space
+        let preview = vbox.add_widget::<preview::Widget>(());
+        vbox.set_child_packing(preview.widget(), true, true, 3, gtk::PackType::Start);
+
+        connect!(relm, file_chooser, connect_file_set(_), Msg::FileSelected);
+        connect!(relm, start_btn, connect_clicked(_), Msg::Start);
+        connect!(relm, pause_btn, connect_clicked(_), Msg::Pause);
+        connect!(relm, resume_btn, connect_clicked(_), Msg::Resume);
+        connect!(relm, cancel_btn, connect_clicked(_), Msg::Cancel);
+        connect!(
+            relm,
+            park_btn,
+            connect_toggled(btn),
+            Msg::SetParkOnPause(btn.get_active())
+        );
+
+        Self {
+            model,
+            widgets: GtkWidgets {
+                root: vbox,
+                file_chooser,
+                start_btn,
+                pause_btn,
+                resume_btn,
+                cancel_btn,
+                label_file,
+                label_state,
+                label_line,
+                label_layer,
+                label_filament,
+                label_size,
+                label_bytes,
+                label_elapsed,
+                label_estimated,
+                label_remaining,
+                label_progress,
+                progress_bar,
+            },
+            preview,
+        }
+    }
+}
+
+/// Format a duration as `h:mm:ss`
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,372 @@
+use std::collections::VecDeque;
+
+use crate::gcode;
+
+/// Number of sent lines that are kept to answer resend requests
+const HISTORY_SIZE: usize = 256;
+
+/// How many lines may wait in the firmware at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StreamingMode {
+    /// Wait for the ok of every line before sending the next one
+    #[default]
+    PingPong,
+    /// Keep the receive buffer of the firmware filled
+    Buffered { rx_buffer_size: usize },
+}
+
+/// A line as it was sent to the printer
+#[derive(Debug, Clone)]
+pub struct SentLine {
+    /// The text sent, including line number and checksum
+    pub text: String,
+    pub command: gcode::Line,
+    /// Index of the line in the print job it came from
+    pub job_line: Option<usize>,
+}
+
+/// The queue of commands waiting to be sent to the printer.
+/// Takes care of line numbers, checksums and resend requests.
+#[derive(Default)]
+pub struct CommandQueue {
+    /// Commands that were not sent yet, with the index of their line in the print job
+    pending: VecDeque<(gcode::Line, Option<usize>)>,
+    /// Already framed lines that have to be sent again
+    resend: VecDeque<SentLine>,
+    /// Lines sent to the printer that are not acknowledged yet
+    in_flight: VecDeque<SentLine>,
+    mode: StreamingMode,
+    /// Line limit derived from the free command buffer slots reported by ADVANCED_OK
+    max_lines_in_flight: Option<usize>,
+    line_numbers: bool,
+    next_line_number: usize,
+    /// Sent lines with their line number
+    history: VecDeque<(usize, SentLine)>,
+    /// Resend requested by the firmware, executed as soon as all lines in flight are acknowledged
+    resend_from: Option<usize>,
+}
+
+impl CommandQueue {
+    /// Wrap every line as `N<line> <cmd>*<checksum>`
+    pub fn set_line_numbers(&mut self, enabled: bool) {
+        if enabled && !self.line_numbers {
+            self.line_numbers = true;
+            self.restart_numbering();
+        } else {
+            self.line_numbers = enabled;
+        }
+    }
+
+    /// Choose between ping-pong and buffered streaming
+    pub fn set_mode(&mut self, mode: StreamingMode) {
+        self.mode = mode;
+        self.max_lines_in_flight = None;
+    }
+
+    /// Add a command at the end of the queue
+    pub fn push(&mut self, command: gcode::Line) {
+        // Comments are not sent
+        if !command.is_empty() {
+            self.pending.push_back((command, None));
+        }
+    }
+
+    /// Add a line of the print job, the index is handed back with the line once acknowledged
+    pub fn push_job_line(&mut self, command: gcode::Line, job_line: usize) {
+        if !command.is_empty() {
+            self.pending.push_back((command, Some(job_line)));
+        }
+    }
+
+    /// True if there is nothing left to send
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.resend.is_empty()
+    }
+
+    /// Drop all commands that were not sent yet.
+    /// The lines in flight are still acknowledged and the lines the firmware asked for again
+    /// are still resent, the line numbers of the firmware would be out of sync otherwise.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Start over after (re)connecting to the printer
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.resend.clear();
+        self.in_flight.clear();
+        self.max_lines_in_flight = None;
+        self.resend_from = None;
+        self.history.clear();
+        if self.line_numbers {
+            self.restart_numbering();
+        }
+    }
+
+    /// Returns the next line to send if the printer is ready for it
+    pub fn next_line(&mut self) -> Option<SentLine> {
+        if self.resend_from.is_some() {
+            return None;
+        }
+        if !self.in_flight.is_empty() {
+            let fits = match self.mode {
+                StreamingMode::PingPong => false,
+                StreamingMode::Buffered { rx_buffer_size } => {
+                    let next_bytes = match (self.resend.front(), self.pending.front()) {
+                        (Some(line), _) => line.text.len() + 1,
+                        // Line number and checksum need some more bytes
+                        (None, Some((command, _))) if self.line_numbers => {
+                            command.code().len() + 16
+                        }
+                        (None, Some((command, _))) => command.code().len() + 1,
+                        (None, None) => return None,
+                    };
+                    let lines_fit = match self.max_lines_in_flight {
+                        Some(max_lines) => self.in_flight.len() < max_lines,
+                        None => true,
+                    };
+                    lines_fit && self.bytes_in_flight() + next_bytes <= rx_buffer_size
+                }
+            };
+            if !fits {
+                return None;
+            }
+        }
+
+        let line = match self.resend.pop_front() {
+            Some(line) => line,
+            None => {
+                let (command, job_line) = self.pending.pop_front()?;
+                if self.line_numbers {
+                    let line_number = self.next_line_number;
+                    self.next_line_number += 1;
+                    let line = SentLine {
+                        text: command.framed(line_number as u32),
+                        command,
+                        job_line,
+                    };
+                    self.history.push_back((line_number, line.clone()));
+                    if self.history.len() > HISTORY_SIZE {
+                        self.history.pop_front();
+                    }
+                    line
+                } else {
+                    SentLine {
+                        text: command.code(),
+                        command,
+                        job_line,
+                    }
+                }
+            }
+        };
+
+        self.in_flight.push_back(line.clone());
+        Some(line)
+    }
+
+    /// The printer acknowledged the oldest line in flight, the payload is everything behind the ok.
+    /// Returns the acknowledged line.
+    pub fn acknowledge(&mut self, payload: &str) -> Option<SentLine> {
+        let acknowledged = self.in_flight.pop_front();
+
+        // ADVANCED_OK reports the free slots of the command buffer as `ok N12 P15 B3`
+        let free_slots = payload
+            .split_whitespace()
+            .filter_map(|word| word.strip_prefix('B'))
+            .find_map(|slots| slots.parse::<usize>().ok());
+        if let Some(free_slots) = free_slots {
+            self.max_lines_in_flight = Some((self.in_flight.len() + free_slots).max(1));
+        }
+
+        // All lines the printer had are handled, now replay the requested ones
+        if self.in_flight.is_empty() {
+            if let Some(line_number) = self.resend_from.take() {
+                self.resend = self
+                    .history
+                    .iter()
+                    .filter(|(number, _)| *number >= line_number)
+                    .map(|(_, line)| line.clone())
+                    .collect();
+            }
+        }
+        acknowledged
+    }
+
+    /// The firmware asks to send everything again starting with the given line.
+    /// Returns false if the line is not in the history anymore.
+    pub fn resend(&mut self, line_number: usize) -> bool {
+        if !self.line_numbers {
+            return false;
+        }
+        let available = match self.history.front() {
+            Some((oldest, _)) => line_number >= *oldest && line_number < self.next_line_number,
+            None => false,
+        };
+        if available {
+            self.resend_from = Some(match self.resend_from {
+                Some(resend_from) => resend_from.min(line_number),
+                None => line_number,
+            });
+        }
+        available
+    }
+
+    /// Bytes waiting in the receive buffer of the firmware, including the newline
+    fn bytes_in_flight(&self) -> usize {
+        self.in_flight.iter().map(|line| line.text.len() + 1).sum()
+    }
+
+    /// Tell the firmware to start counting at zero again
+    fn restart_numbering(&mut self) {
+        self.next_line_number = 0;
+        self.pending
+            .push_front((gcode::Line::command('M', 110).with('N', 0.0), None));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn move_to(x: usize) -> gcode::Line {
+        gcode::Line::command('G', 1).with('X', x as f64)
+    }
+
+    /// Everything the queue hands out right now
+    fn send_all(queue: &mut CommandQueue) -> Vec<String> {
+        let mut sent = Vec::new();
+        while let Some(line) = queue.next_line() {
+            sent.push(line.text);
+        }
+        sent
+    }
+
+    /// Bytes the lines take in the receive buffer of the firmware
+    fn bytes(lines: &[String]) -> usize {
+        lines.iter().map(|line| line.len() + 1).sum()
+    }
+
+    #[test]
+    fn ping_pong() {
+        let mut queue = CommandQueue::default();
+        for x in 1..=3 {
+            queue.push(move_to(x));
+        }
+        assert_eq!(send_all(&mut queue), vec!["G1 X1"]);
+        queue.acknowledge("");
+        assert_eq!(send_all(&mut queue), vec!["G1 X2"]);
+    }
+
+    #[test]
+    fn fill_receive_buffer() {
+        let mut queue = CommandQueue::default();
+        queue.set_mode(StreamingMode::Buffered { rx_buffer_size: 20 });
+        for x in 1..=5 {
+            queue.push(move_to(x));
+        }
+        // Three lines of 6 bytes fit into 20 bytes, a fourth does not
+        assert_eq!(send_all(&mut queue), vec!["G1 X1", "G1 X2", "G1 X3"]);
+        queue.acknowledge("");
+        assert_eq!(send_all(&mut queue), vec!["G1 X4"]);
+
+        // Line number and checksum are guessed with 16 bytes before the line is framed
+        let mut queue = CommandQueue::default();
+        queue.set_line_numbers(true);
+        queue.set_mode(StreamingMode::Buffered { rx_buffer_size: 64 });
+        for x in 1..=5 {
+            queue.push(move_to(x));
+        }
+        let sent = send_all(&mut queue);
+        // M110 and three moves
+        assert_eq!(sent.len(), 4);
+        assert!(bytes(&sent) <= 64);
+        assert!(bytes(&sent) + "G1 X4".len() + 16 > 64);
+    }
+
+    #[test]
+    fn advanced_ok() {
+        let mut queue = CommandQueue::default();
+        queue.set_mode(StreamingMode::Buffered {
+            rx_buffer_size: 1024,
+        });
+        for x in 1..=10 {
+            queue.push(move_to(x));
+        }
+        assert_eq!(send_all(&mut queue).len(), 10);
+        for x in 11..=20 {
+            queue.push(move_to(x));
+        }
+        // One free slot in the command buffer of the firmware
+        queue.acknowledge("N1 P15 B1");
+        assert_eq!(send_all(&mut queue), vec!["G1 X11"]);
+        queue.acknowledge("N2 P15 B0");
+        assert!(send_all(&mut queue).is_empty());
+        queue.acknowledge("N3 P15 B3");
+        assert_eq!(send_all(&mut queue).len(), 3);
+    }
+
+    #[test]
+    fn resend_rejected_line() {
+        let mut queue = CommandQueue::default();
+        queue.set_line_numbers(true);
+        queue.set_mode(StreamingMode::Buffered {
+            rx_buffer_size: 1024,
+        });
+        for x in 1..=4 {
+            queue.push(move_to(x));
+        }
+        let sent = send_all(&mut queue);
+        assert_eq!(sent.len(), 5);
+        assert_eq!(sent[0], "N0 M110 N0*125");
+        assert_eq!(sent[2], move_to(2).framed(2));
+
+        // Line 2 arrived garbled, the firmware rejects it and every line after it
+        queue.acknowledge("");
+        queue.acknowledge("");
+        assert!(queue.resend(2));
+        queue.acknowledge("");
+        // Nothing new is sent while the rejected lines are still in flight
+        queue.push(move_to(5));
+        assert!(queue.next_line().is_none());
+        assert!(queue.resend(2));
+        queue.acknowledge("");
+        // A later request is merged into the earlier one
+        assert!(queue.resend(3));
+        queue.acknowledge("");
+
+        let mut again = send_all(&mut queue);
+        assert_eq!(again[..3], sent[2..]);
+        assert_eq!(again.pop(), Some(move_to(5).framed(5)));
+    }
+
+    #[test]
+    fn resend_outside_history() {
+        let mut queue = CommandQueue::default();
+        // Without line numbers the firmware cannot ask for a line
+        queue.push(move_to(1));
+        send_all(&mut queue);
+        queue.acknowledge("");
+        assert!(!queue.resend(0));
+
+        // The M110 is line 0, the moves follow
+        queue.set_line_numbers(true);
+        for x in 1..=HISTORY_SIZE + 10 {
+            queue.push(move_to(x));
+            while queue.next_line().is_some() {
+                queue.acknowledge("");
+            }
+        }
+        // The M110 and the first lines dropped out of the history
+        assert!(!queue.resend(0));
+        assert!(!queue.resend(10));
+        assert!(queue.resend(HISTORY_SIZE));
+        // Not sent yet
+        assert!(!queue.resend(HISTORY_SIZE + 20));
+
+        // After a reset the numbering starts over with M110
+        queue.reset();
+        assert!(!queue.resend(HISTORY_SIZE));
+        queue.push(move_to(1));
+        assert_eq!(send_all(&mut queue), vec!["N0 M110 N0*125".to_string()]);
+    }
+}
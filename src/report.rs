@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+
+/// A heater the firmware reports a temperature for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Heater {
+    Hotend(usize),
+    Bed,
+    Chamber,
+}
+
+impl Heater {
+    /// Label shown in front of the readout
+    pub fn label(&self) -> String {
+        match self {
+            Heater::Hotend(index) => format!("E{}:", index + 1),
+            Heater::Bed => "Bed:".to_string(),
+            Heater::Chamber => "Chamber:".to_string(),
+        }
+    }
+}
+
+/// Temperature, target and power of a single heater
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeaterReading {
+    pub actual: f32,
+    pub target: Option<f32>,
+    /// Raw heater power as reported behind `@:` (0 - 127 on Marlin)
+    pub power: Option<u8>,
+}
+
+impl HeaterReading {
+    /// Heater power in percent
+    pub fn power_percent(&self) -> Option<f32> {
+        self.power.map(|power| f32::from(power) * 100.0 / 127.0)
+    }
+}
+
+/// A parsed temperature report like `T:210.3 /210.0 B:60.1 /60.0 @:127 B@:0`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TemperatureReport {
+    pub heaters: BTreeMap<Heater, HeaterReading>,
+}
+
+impl TemperatureReport {
+    /// Parse a temperature report, the leading `ok` is optional.
+    /// Returns `None` if the line does not contain any heater.
+    pub fn parse(line: &str) -> Option<TemperatureReport> {
+        let mut heaters = BTreeMap::new();
+        // Plain `T:` and `@:` refer to the active hotend and are only used if there are no indexed values
+        let mut active_hotend: Option<HeaterReading> = None;
+        let mut active_power: Option<u8> = None;
+        let mut powers = BTreeMap::new();
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let mut index = 0;
+        while index < tokens.len() {
+            let token = tokens[index];
+            index += 1;
+
+            let (key, value) = match token.find(':') {
+                Some(pos) => (&token[..pos], &token[pos + 1..]),
+                None => continue,
+            };
+
+            if let Some(heater) = key.strip_suffix('@') {
+                // Power of a heater: `@:`, `@0:`, `B@:` or `C@:`
+                let power = match value.parse::<f32>() {
+                    Ok(power) => power.clamp(0.0, 255.0) as u8,
+                    Err(_) => continue,
+                };
+                match heater {
+                    "" => active_power = Some(power),
+                    "B" => {
+                        powers.insert(Heater::Bed, power);
+                    }
+                    "C" => {
+                        powers.insert(Heater::Chamber, power);
+                    }
+                    _ => (),
+                }
+            } else if let Some(hotend) = key.strip_prefix('@') {
+                if let (Ok(hotend), Ok(power)) = (hotend.parse::<usize>(), value.parse::<f32>()) {
+                    powers.insert(Heater::Hotend(hotend), power.clamp(0.0, 255.0) as u8);
+                }
+            } else {
+                let heater = match key {
+                    "T" => None,
+                    "B" => Some(Heater::Bed),
+                    "C" => Some(Heater::Chamber),
+                    _ => match key.strip_prefix('T').map(str::parse::<usize>) {
+                        Some(Ok(hotend)) => Some(Heater::Hotend(hotend)),
+                        _ => continue,
+                    },
+                };
+
+                // The target may be attached (`210.0/215.0`) or follow as next token (`/215.0`)
+                let (actual, mut target) = match value.find('/') {
+                    Some(pos) => (&value[..pos], Some(&value[pos + 1..])),
+                    None => (value, None),
+                };
+                if target.is_none() {
+                    if let Some(next) = tokens.get(index).and_then(|t| t.strip_prefix('/')) {
+                        index += 1;
+                        target = Some(next);
+                    }
+                }
+
+                let actual = match actual.parse::<f32>() {
+                    Ok(actual) => actual,
+                    Err(_) => continue,
+                };
+                let reading = HeaterReading {
+                    actual,
+                    target: target.and_then(|t| t.parse::<f32>().ok()),
+                    power: None,
+                };
+
+                match heater {
+                    Some(heater) => {
+                        heaters.insert(heater, reading);
+                    }
+                    None => active_hotend = Some(reading),
+                }
+            }
+        }
+
+        // Only a single hotend is reported without an index
+        let has_indexed_hotend = heaters.keys().any(|h| matches!(h, Heater::Hotend(_)));
+        if let Some(reading) = active_hotend {
+            if !has_indexed_hotend {
+                heaters.insert(Heater::Hotend(0), reading);
+            }
+        }
+        if let Some(power) = active_power {
+            powers.entry(Heater::Hotend(0)).or_insert(power);
+        }
+        for (heater, power) in powers {
+            if let Some(reading) = heaters.get_mut(&heater) {
+                reading.power = Some(power);
+            }
+        }
+
+        if heaters.is_empty() {
+            None
+        } else {
+            Some(TemperatureReport { heaters })
+        }
+    }
+}
+
+/// A parsed position report like `X:10.00 Y:20.00 Z:0.30 E:0.00 Count X:800 Y:1600 Z:120`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PositionReport {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub e: Option<f32>,
+    /// Stepper positions of X, Y and Z as reported behind `Count`
+    pub count: Option<[f32; 3]>,
+}
+
+impl PositionReport {
+    /// Parse a position report, the leading `ok` is optional.
+    /// Returns `None` if the line does not contain the X, Y and Z position.
+    pub fn parse(line: &str) -> Option<PositionReport> {
+        let (logical, count) = match line.find("Count") {
+            Some(pos) => (&line[..pos], Some(&line[pos + "Count".len()..])),
+            None => (line, None),
+        };
+
+        let values = parse_axis_values(logical);
+
+        let count = count.and_then(|count| {
+            let values = parse_axis_values(count);
+            match (
+                find_axis(&values, "X"),
+                find_axis(&values, "Y"),
+                find_axis(&values, "Z"),
+            ) {
+                (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+                // RepRapFirmware reports the counts without axis names: `Count 800 1600 120`
+                _ => {
+                    let numbers: Vec<f32> = count
+                        .split_whitespace()
+                        .take_while(|word| word.parse::<f32>().is_ok())
+                        .filter_map(|word| word.parse::<f32>().ok())
+                        .collect();
+                    if numbers.len() >= 3 {
+                        Some([numbers[0], numbers[1], numbers[2]])
+                    } else {
+                        None
+                    }
+                }
+            }
+        });
+
+        Some(PositionReport {
+            x: find_axis(&values, "X")?,
+            y: find_axis(&values, "Y")?,
+            z: find_axis(&values, "Z")?,
+            e: find_axis(&values, "E"),
+            count,
+        })
+    }
+}
+
+/// Collect all `KEY:value` pairs of a line, the pairs don't need to be separated by whitespace (`X:0.00Y:0.00`)
+fn parse_axis_values(text: &str) -> Vec<(String, f32)> {
+    let mut values = Vec::new();
+    for (pos, _) in text.match_indices(':') {
+        let key: String = text[..pos]
+            .chars()
+            .rev()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<Vec<char>>()
+            .into_iter()
+            .rev()
+            .collect();
+        let value: String = text[pos + 1..]
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '-' || *c == '+')
+            .collect();
+        if let Ok(value) = value.parse::<f32>() {
+            // Drop the digits of the previous value that got glued to the key
+            let key = key.trim_start_matches(|c: char| !c.is_ascii_alphabetic());
+            values.push((key.to_string(), value));
+        }
+    }
+    values
+}
+
+fn find_axis(values: &[(String, f32)], name: &str) -> Option<f32> {
+    values
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| *value)
+}
@@ -0,0 +1,249 @@
+use crate::report;
+
+/// A classified line received from the printer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// `ok` with everything that follows it (e.g. temperatures or `N12 P15 B3`)
+    Ok(String),
+    /// `busy: processing` and friends, the firmware is still alive but working
+    Busy(String),
+    /// Informational output like `echo:` lines or Klipper `//` comments
+    Echo(String),
+    /// `Error:` or Klipper's `!!`
+    Error(String),
+    /// The firmware asks to resend everything starting with the given line number
+    Resend(usize),
+    /// The firmware has (re)started
+    Start,
+    /// The firmware is idle and waits for commands
+    Wait,
+    Temperature(report::TemperatureReport),
+    Position(report::PositionReport),
+    /// Host action commands like `//action:pause`
+    Action(String),
+    Unknown(String),
+}
+
+impl Response {
+    /// Classify a single line received from the printer
+    pub fn parse(line: &str) -> Response {
+        let line = line.trim();
+
+        if let Some(payload) = strip_keyword(line, "ok") {
+            return Response::Ok(payload.trim().to_string());
+        }
+
+        if let Some(resend) =
+            strip_prefix_ignore_case(line, "Resend:").or_else(|| strip_keyword(line, "rs"))
+        {
+            let number: String = resend
+                .trim()
+                .trim_start_matches(['N', ':'])
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            if let Ok(number) = number.parse() {
+                return Response::Resend(number);
+            }
+        }
+
+        if let Some(action) = line
+            .strip_prefix("//action:")
+            .or_else(|| line.strip_prefix("// action:"))
+        {
+            return Response::Action(action.trim().to_string());
+        }
+
+        if let Some(error) =
+            strip_prefix_ignore_case(line, "Error:").or_else(|| line.strip_prefix("!!"))
+        {
+            return Response::Error(error.trim().to_string());
+        }
+
+        // Marlin 2 prefixes the busy message with echo:
+        let echo = strip_prefix_ignore_case(line, "echo:");
+        if let Some(busy) = echo.unwrap_or(line).trim().strip_prefix("busy:") {
+            return Response::Busy(busy.trim().to_string());
+        }
+        if let Some(echo) = echo.or_else(|| line.strip_prefix("//")) {
+            return Response::Echo(echo.trim().to_string());
+        }
+        if line.starts_with("Warning:") {
+            return Response::Echo(line.to_string());
+        }
+
+        if line == "start" {
+            return Response::Start;
+        }
+        if line == "wait" {
+            return Response::Wait;
+        }
+
+        if let Some(report) = report::TemperatureReport::parse(line) {
+            return Response::Temperature(report);
+        }
+        if let Some(report) = report::PositionReport::parse(line) {
+            return Response::Position(report);
+        }
+
+        Response::Unknown(line.to_string())
+    }
+}
+
+/// Strip a keyword that has to be followed by whitespace or the end of the line
+fn strip_keyword<'a>(line: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(keyword)?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    if line.len() >= prefix.len()
+        && line.is_char_boundary(prefix.len())
+        && line[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        Some(&line[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(transcript: &[&str]) -> Vec<Response> {
+        transcript
+            .iter()
+            .map(|line| Response::parse(line))
+            .collect()
+    }
+
+    #[test]
+    fn short_lines() {
+        assert_eq!(Response::parse(""), Response::Unknown("".to_string()));
+        assert_eq!(Response::parse("o"), Response::Unknown("o".to_string()));
+        assert_eq!(Response::parse("ok"), Response::Ok("".to_string()));
+        assert_eq!(Response::parse("ok\r"), Response::Ok("".to_string()));
+    }
+
+    #[test]
+    fn marlin_transcript() {
+        let responses = classify(&[
+            "start",
+            "echo:Marlin 2.0.9.3",
+            "echo: Last Updated: 2021-12-28 | Author: (none, default config)",
+            "ok T:210.3 /210.0 B:60.1 /60.0 @:127 B@:0",
+            "X:10.00 Y:20.00 Z:0.30 E:0.00 Count X:800 Y:1600 Z:120",
+            "ok",
+            "echo:busy: processing",
+            " T:185.52 /210.00 B:60.06 /60.00 @:127 B@:0 W:?",
+            "Error:checksum mismatch, Last Line: 4",
+            "Resend: 5",
+            "ok N12 P15 B3",
+            "//action:pause",
+            "wait",
+            "echo:Unknown command: \"G999\"",
+        ]);
+
+        assert_eq!(responses[0], Response::Start);
+        assert_eq!(responses[1], Response::Echo("Marlin 2.0.9.3".to_string()));
+        assert!(matches!(responses[2], Response::Echo(_)));
+        assert_eq!(
+            responses[3],
+            Response::Ok("T:210.3 /210.0 B:60.1 /60.0 @:127 B@:0".to_string())
+        );
+        match &responses[4] {
+            Response::Position(report) => {
+                assert_eq!(report.x, 10.0);
+                assert_eq!(report.y, 20.0);
+                assert_eq!(report.z, 0.3);
+                assert_eq!(report.count, Some([800.0, 1600.0, 120.0]));
+            }
+            other => panic!("expected position, got {:?}", other),
+        }
+        assert_eq!(responses[5], Response::Ok("".to_string()));
+        assert_eq!(responses[6], Response::Busy("processing".to_string()));
+        match &responses[7] {
+            Response::Temperature(report) => {
+                let hotend = report.heaters[&report::Heater::Hotend(0)];
+                assert_eq!(hotend.actual, 185.52);
+                assert_eq!(hotend.target, Some(210.0));
+                assert_eq!(hotend.power, Some(127));
+            }
+            other => panic!("expected temperature, got {:?}", other),
+        }
+        assert_eq!(
+            responses[8],
+            Response::Error("checksum mismatch, Last Line: 4".to_string())
+        );
+        assert_eq!(responses[9], Response::Resend(5));
+        assert_eq!(responses[10], Response::Ok("N12 P15 B3".to_string()));
+        assert_eq!(responses[11], Response::Action("pause".to_string()));
+        assert_eq!(responses[12], Response::Wait);
+        assert!(matches!(responses[13], Response::Echo(_)));
+    }
+
+    #[test]
+    fn reprapfirmware_transcript() {
+        let responses = classify(&[
+            "ok T:25.0 /0.0 B:24.9 /0.0",
+            "X:0.000 Y:0.000 Z:0.000 E:0.000 E0:-0.0 Count 0 0 0 Machine 0.000 0.000 0.000 Bed comp 0.000",
+            "ok",
+            "Error: G0/G1: insufficient axes homed",
+            "Warning: Heater 0 appears to be faulty",
+            "rs N7",
+        ]);
+
+        assert!(matches!(responses[0], Response::Ok(_)));
+        match &responses[1] {
+            Response::Position(report) => assert_eq!(report.count, Some([0.0, 0.0, 0.0])),
+            other => panic!("expected position, got {:?}", other),
+        }
+        assert_eq!(responses[2], Response::Ok("".to_string()));
+        assert_eq!(
+            responses[3],
+            Response::Error("G0/G1: insufficient axes homed".to_string())
+        );
+        assert!(matches!(responses[4], Response::Echo(_)));
+        assert_eq!(responses[5], Response::Resend(7));
+    }
+
+    #[test]
+    fn klipper_transcript() {
+        let responses = classify(&[
+            "// Klipper state: Ready",
+            "ok B:22.5 /0.0 T0:22.3 /0.0",
+            "!! Must home axis first: 10.000 0.000 0.000 [0.000]",
+            "ok",
+            "X:0.000 Y:0.000 Z:0.000 E:0.000",
+            "// action:cancel",
+        ]);
+
+        assert_eq!(
+            responses[0],
+            Response::Echo("Klipper state: Ready".to_string())
+        );
+        match &responses[1] {
+            Response::Ok(payload) => {
+                let report = report::TemperatureReport::parse(payload).unwrap();
+                assert_eq!(report.heaters.len(), 2);
+                assert_eq!(report.heaters[&report::Heater::Bed].actual, 22.5);
+            }
+            other => panic!("expected ok, got {:?}", other),
+        }
+        assert_eq!(
+            responses[2],
+            Response::Error("Must home axis first: 10.000 0.000 0.000 [0.000]".to_string())
+        );
+        assert_eq!(responses[3], Response::Ok("".to_string()));
+        match &responses[4] {
+            Response::Position(report) => assert_eq!(report.count, None),
+            other => panic!("expected position, got {:?}", other),
+        }
+        assert_eq!(responses[5], Response::Action("cancel".to_string()));
+    }
+}
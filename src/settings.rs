@@ -0,0 +1,782 @@
+use gtk::prelude::*;
+use relm::{connect, Relm};
+use relm_derive::Msg;
+
+use crate::config;
+use crate::firmware;
+
+#[derive(Msg)]
+pub enum Msg {
+    Changed,
+    AddProfile,
+    RemoveProfile,
+    /// Show a config that was changed somewhere else, e.g. the profile chosen in the connection bar
+    Update(config::Config),
+    SetConfig(config::Config),
+    /// What the connected printer reported about its firmware
+    SetCapabilities(firmware::Capabilities),
+}
+
+pub struct Model {
+    stream: relm::EventStream<Msg>,
+    config: config::Config,
+}
+
+struct GtkWidgets {
+    root: gtk::Box,
+    data_bits_combobox: gtk::ComboBoxText,
+    parity_combobox: gtk::ComboBoxText,
+    stop_bits_combobox: gtk::ComboBoxText,
+    flow_control_combobox: gtk::ComboBoxText,
+    timeout_spin: gtk::SpinButton,
+    handshake_timeout_spin: gtk::SpinButton,
+    temperature_interval_spin: gtk::SpinButton,
+    position_interval_spin: gtk::SpinButton,
+    xy_step_spin: gtk::SpinButton,
+    z_step_spin: gtk::SpinButton,
+    e_step_spin: gtk::SpinButton,
+    xy_feedrate_spin: gtk::SpinButton,
+    z_feedrate_spin: gtk::SpinButton,
+    e_feedrate_spin: gtk::SpinButton,
+    maximized_btn: gtk::CheckButton,
+    dark_theme_btn: gtk::CheckButton,
+    profile: ProfileWidgets,
+    firmware: FirmwareWidgets,
+}
+
+/// The fields of the active printer profile
+struct ProfileWidgets {
+    name_entry: gtk::Entry,
+    serial_number_entry: gtk::Entry,
+    flavour_combobox: gtk::ComboBoxText,
+    volume_spins: [gtk::SpinButton; 3],
+    axis_min_spins: [gtk::SpinButton; 3],
+    extruders_spin: gtk::SpinButton,
+    heated_bed_btn: gtk::CheckButton,
+    heated_chamber_btn: gtk::CheckButton,
+    max_hotend_spin: gtk::SpinButton,
+    max_bed_spin: gtk::SpinButton,
+    max_chamber_spin: gtk::SpinButton,
+    filament_diameter_spin: gtk::SpinButton,
+    filament_density_spin: gtk::SpinButton,
+    start_script_view: gtk::TextView,
+    end_script_view: gtk::TextView,
+    cancel_script_view: gtk::TextView,
+    remove_btn: gtk::Button,
+}
+
+/// The answer of the connected printer to `M115`
+struct FirmwareWidgets {
+    name_label: gtk::Label,
+    machine_type_label: gtk::Label,
+    extruders_label: gtk::Label,
+    uuid_label: gtk::Label,
+    capabilities_label: gtk::Label,
+}
+
+pub struct Widget {
+    model: Model,
+    widgets: GtkWidgets,
+}
+
+impl relm::Update for Widget {
+    type Model = Model;
+    type ModelParam = config::Config;
+    type Msg = Msg;
+
+    fn model(relm: &Relm<Self>, config: Self::ModelParam) -> Self::Model {
+        Model {
+            stream: relm.stream().clone(),
+            config,
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            Msg::Changed => {
+                let config = read_config(&self.widgets, &self.model.config);
+                // Showing a config changes the widgets as well
+                if config != self.model.config {
+                    self.model.config = config;
+                    self.model
+                        .stream
+                        .emit(Msg::SetConfig(self.model.config.clone()));
+                }
+            }
+            Msg::AddProfile => {
+                let config = &mut self.model.config;
+                let name = (config.profiles.len() + 1..)
+                    .map(|number| format!("Printer {}", number))
+                    .find(|name| config.profiles.iter().all(|profile| profile.name != *name))
+                    .unwrap_or_default();
+                config.profiles.push(config::Profile {
+                    name: name.clone(),
+                    ..config::Profile::default()
+                });
+                config.active_profile = name;
+                show_config(&self.widgets, &self.model.config);
+                self.model
+                    .stream
+                    .emit(Msg::SetConfig(self.model.config.clone()));
+            }
+            Msg::RemoveProfile => {
+                let config = &mut self.model.config;
+                if config.profiles.len() > 1 {
+                    let active = config.profile().name.clone();
+                    config.profiles.retain(|profile| profile.name != active);
+                    config.active_profile = config.profiles[0].name.clone();
+                    show_config(&self.widgets, &self.model.config);
+                    self.model
+                        .stream
+                        .emit(Msg::SetConfig(self.model.config.clone()));
+                }
+            }
+            Msg::Update(config) => {
+                show_config(&self.widgets, &config);
+                self.model.config = config;
+            }
+            Msg::SetConfig(_config) => (),
+            Msg::SetCapabilities(capabilities) => {
+                show_capabilities(&self.widgets.firmware, &capabilities)
+            }
+        }
+    }
+}
+
+impl relm::Widget for Widget {
+    type Root = gtk::Box;
+
+    fn root(&self) -> Self::Root {
+        self.widgets.root.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        // The root widget, the settings can be scrolled
+        let root = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let scrolled_window =
+            gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        scrolled_window.add(&vbox);
+        root.pack_start(&scrolled_window, true, true, 0);
+
+        // The printer profile chosen in the connection bar
+        let name_entry = gtk::Entry::new();
+        let serial_number_entry = gtk::Entry::new();
+        serial_number_entry.set_placeholder_text(Some("Any"));
+        let flavour_combobox = gtk::ComboBoxText::new();
+        for (index, flavour) in config::Flavour::ALL.iter().enumerate() {
+            flavour_combobox.append(Some(&index.to_string()), flavour.name());
+        }
+        let volume_spins = [
+            create_spin(1.0, 2000.0, 1.0),
+            create_spin(1.0, 2000.0, 1.0),
+            create_spin(1.0, 2000.0, 1.0),
+        ];
+        let volume_box = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+        for (spin, label) in volume_spins.iter().zip(["X", "Y", "Z"].iter()) {
+            volume_box.pack_start(&gtk::Label::new(Some(label)), false, false, 0);
+            volume_box.pack_start(spin, false, false, 0);
+        }
+        let axis_min_spins = [
+            create_spin(-100.0, 100.0, 0.1),
+            create_spin(-100.0, 100.0, 0.1),
+            create_spin(-100.0, 100.0, 0.1),
+        ];
+        let axis_min_box = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+        for (spin, label) in axis_min_spins.iter().zip(["X", "Y", "Z"].iter()) {
+            axis_min_box.pack_start(&gtk::Label::new(Some(label)), false, false, 0);
+            axis_min_box.pack_start(spin, false, false, 0);
+        }
+        let extruders_spin = create_spin(1.0, 8.0, 1.0);
+        let heated_bed_btn = gtk::CheckButton::with_label("Heated bed");
+        let heated_chamber_btn = gtk::CheckButton::with_label("Heated chamber");
+        let max_hotend_spin = create_spin(0.0, 500.0, 5.0);
+        let max_bed_spin = create_spin(0.0, 200.0, 5.0);
+        let max_chamber_spin = create_spin(0.0, 200.0, 5.0);
+        let filament_diameter_spin = create_spin(1.0, 3.0, 0.05);
+        let filament_density_spin = create_spin(0.5, 3.0, 0.01);
+        let start_script_view = create_script_view();
+        let end_script_view = create_script_view();
+        let cancel_script_view = create_script_view();
+
+        let add_btn = gtk::Button::with_label("New printer");
+        let remove_btn = gtk::Button::with_label("Remove printer");
+        remove_btn
+            .get_style_context()
+            .add_class("destructive-action");
+        let profile_btn_box = gtk::Box::new(gtk::Orientation::Horizontal, 3);
+        profile_btn_box.pack_start(&add_btn, false, false, 0);
+        profile_btn_box.pack_start(&remove_btn, false, false, 0);
+
+        vbox.pack_start(
+            &create_frame(
+                "Printer",
+                &[
+                    ("", profile_btn_box.upcast()),
+                    ("Name:", name_entry.clone().upcast()),
+                    ("USB serial number:", serial_number_entry.clone().upcast()),
+                    ("Firmware:", flavour_combobox.clone().upcast()),
+                    ("Build volume (mm):", volume_box.upcast()),
+                    ("Axis minimum (mm):", axis_min_box.upcast()),
+                    ("Extruders:", extruders_spin.clone().upcast()),
+                    ("", heated_bed_btn.clone().upcast()),
+                    ("", heated_chamber_btn.clone().upcast()),
+                    ("Max. hotend (°C):", max_hotend_spin.clone().upcast()),
+                    ("Max. bed (°C):", max_bed_spin.clone().upcast()),
+                    ("Max. chamber (°C):", max_chamber_spin.clone().upcast()),
+                    (
+                        "Filament diameter (mm):",
+                        filament_diameter_spin.clone().upcast(),
+                    ),
+                    (
+                        "Filament density (g/cm³):",
+                        filament_density_spin.clone().upcast(),
+                    ),
+                    ("Start script:", start_script_view.clone().upcast()),
+                    ("End script:", end_script_view.clone().upcast()),
+                    ("Cancel script:", cancel_script_view.clone().upcast()),
+                ],
+            ),
+            false,
+            false,
+            3,
+        );
+
+        // Filled in after connecting
+        let firmware = FirmwareWidgets {
+            name_label: gtk::Label::new(None),
+            machine_type_label: gtk::Label::new(None),
+            extruders_label: gtk::Label::new(None),
+            uuid_label: gtk::Label::new(None),
+            capabilities_label: gtk::Label::new(None),
+        };
+        firmware.uuid_label.set_selectable(true);
+        show_capabilities(&firmware, &firmware::Capabilities::default());
+
+        vbox.pack_start(
+            &create_frame(
+                "Connected printer",
+                &[
+                    ("Firmware:", firmware.name_label.clone().upcast()),
+                    (
+                        "Machine type:",
+                        firmware.machine_type_label.clone().upcast(),
+                    ),
+                    ("Extruders:", firmware.extruders_label.clone().upcast()),
+                    ("UUID:", firmware.uuid_label.clone().upcast()),
+                    (
+                        "Capabilities:",
+                        firmware.capabilities_label.clone().upcast(),
+                    ),
+                ],
+            ),
+            false,
+            false,
+            3,
+        );
+
+        // Serial parameters, the baud rate is chosen in the connection bar
+        let data_bits_combobox = create_combobox(&[("5", "5"), ("6", "6"), ("7", "7"), ("8", "8")]);
+        let parity_combobox =
+            create_combobox(&[("none", "None"), ("odd", "Odd"), ("even", "Even")]);
+        let stop_bits_combobox = create_combobox(&[("1", "1"), ("2", "2")]);
+        let flow_control_combobox = create_combobox(&[
+            ("none", "None"),
+            ("software", "Software (XON/XOFF)"),
+            ("hardware", "Hardware (RTS/CTS)"),
+        ]);
+        let timeout_spin = create_spin(1.0, 1000.0, 1.0);
+        let handshake_timeout_spin = create_spin(1000.0, 120_000.0, 1000.0);
+
+        vbox.pack_start(
+            &create_frame(
+                "Serial port",
+                &[
+                    ("Data bits:", data_bits_combobox.clone().upcast()),
+                    ("Parity:", parity_combobox.clone().upcast()),
+                    ("Stop bits:", stop_bits_combobox.clone().upcast()),
+                    ("Flow control:", flow_control_combobox.clone().upcast()),
+                    ("Read timeout (ms):", timeout_spin.clone().upcast()),
+                    (
+                        "Handshake timeout (ms):",
+                        handshake_timeout_spin.clone().upcast(),
+                    ),
+                ],
+            ),
+            false,
+            false,
+            3,
+        );
+
+        // Status queries, zero turns them off
+        let temperature_interval_spin = create_spin(0.0, 60000.0, 100.0);
+        let position_interval_spin = create_spin(0.0, 60000.0, 100.0);
+
+        vbox.pack_start(
+            &create_frame(
+                "Polling",
+                &[
+                    (
+                        "Temperature every (ms):",
+                        temperature_interval_spin.clone().upcast(),
+                    ),
+                    (
+                        "Position every (ms):",
+                        position_interval_spin.clone().upcast(),
+                    ),
+                ],
+            ),
+            false,
+            false,
+            3,
+        );
+
+        // Jog buttons of the Move tab
+        let xy_step_spin = create_spin(0.01, 100.0, 0.1);
+        let z_step_spin = create_spin(0.01, 100.0, 0.1);
+        let e_step_spin = create_spin(0.01, 100.0, 0.1);
+        let xy_feedrate_spin = create_spin(1.0, 20000.0, 100.0);
+        let z_feedrate_spin = create_spin(1.0, 20000.0, 100.0);
+        let e_feedrate_spin = create_spin(1.0, 20000.0, 100.0);
+
+        vbox.pack_start(
+            &create_frame(
+                "Jog",
+                &[
+                    ("XY step (mm):", xy_step_spin.clone().upcast()),
+                    ("Z step (mm):", z_step_spin.clone().upcast()),
+                    ("Extruder step (mm):", e_step_spin.clone().upcast()),
+                    ("XY feedrate (mm/min):", xy_feedrate_spin.clone().upcast()),
+                    ("Z feedrate (mm/min):", z_feedrate_spin.clone().upcast()),
+                    (
+                        "Extruder feedrate (mm/min):",
+                        e_feedrate_spin.clone().upcast(),
+                    ),
+                ],
+            ),
+            false,
+            false,
+            3,
+        );
+
+        // Interface preferences
+        let maximized_btn = gtk::CheckButton::with_label("Start maximized");
+        let dark_theme_btn = gtk::CheckButton::with_label("Dark theme");
+
+        vbox.pack_start(
+            &create_frame(
+                "Interface",
+                &[
+                    ("", maximized_btn.clone().upcast()),
+                    ("", dark_theme_btn.clone().upcast()),
+                ],
+            ),
+            false,
+            false,
+            3,
+        );
+
+        let widgets = GtkWidgets {
+            root,
+            data_bits_combobox,
+            parity_combobox,
+            stop_bits_combobox,
+            flow_control_combobox,
+            timeout_spin,
+            handshake_timeout_spin,
+            temperature_interval_spin,
+            position_interval_spin,
+            xy_step_spin,
+            z_step_spin,
+            e_step_spin,
+            xy_feedrate_spin,
+            z_feedrate_spin,
+            e_feedrate_spin,
+            maximized_btn,
+            dark_theme_btn,
+            profile: ProfileWidgets {
+                name_entry,
+                serial_number_entry,
+                flavour_combobox,
+                volume_spins,
+                axis_min_spins,
+                extruders_spin,
+                heated_bed_btn,
+                heated_chamber_btn,
+                max_hotend_spin,
+                max_bed_spin,
+                max_chamber_spin,
+                filament_diameter_spin,
+                filament_density_spin,
+                start_script_view,
+                end_script_view,
+                cancel_script_view,
+                remove_btn,
+            },
+            firmware,
+        };
+        show_config(&widgets, &model.config);
+
+        // Connect the signals after the config is shown
+        let profile = &widgets.profile;
+        connect!(relm, add_btn, connect_clicked(_), Msg::AddProfile);
+        connect!(
+            relm,
+            profile.remove_btn,
+            connect_clicked(_),
+            Msg::RemoveProfile
+        );
+        for entry in [&profile.name_entry, &profile.serial_number_entry].iter() {
+            connect!(relm, entry, connect_changed(_), Msg::Changed);
+        }
+        for combobox in [
+            &profile.flavour_combobox,
+            &widgets.data_bits_combobox,
+            &widgets.parity_combobox,
+            &widgets.stop_bits_combobox,
+            &widgets.flow_control_combobox,
+        ]
+        .iter()
+        {
+            connect!(relm, combobox, connect_changed(_), Msg::Changed);
+        }
+        for spin in profile
+            .volume_spins
+            .iter()
+            .chain(profile.axis_min_spins.iter())
+            .chain(
+                [
+                    &profile.extruders_spin,
+                    &profile.max_hotend_spin,
+                    &profile.max_bed_spin,
+                    &profile.max_chamber_spin,
+                    &profile.filament_diameter_spin,
+                    &profile.filament_density_spin,
+                    &widgets.timeout_spin,
+                    &widgets.handshake_timeout_spin,
+                    &widgets.temperature_interval_spin,
+                    &widgets.position_interval_spin,
+                    &widgets.xy_step_spin,
+                    &widgets.z_step_spin,
+                    &widgets.e_step_spin,
+                    &widgets.xy_feedrate_spin,
+                    &widgets.z_feedrate_spin,
+                    &widgets.e_feedrate_spin,
+                ]
+                .iter()
+                .cloned(),
+            )
+        {
+            connect!(relm, spin, connect_value_changed(_), Msg::Changed);
+        }
+        for btn in [
+            &profile.heated_bed_btn,
+            &profile.heated_chamber_btn,
+            &widgets.maximized_btn,
+            &widgets.dark_theme_btn,
+        ]
+        .iter()
+        {
+            connect!(relm, btn, connect_toggled(_), Msg::Changed);
+        }
+        for view in [
+            &profile.start_script_view,
+            &profile.end_script_view,
+            &profile.cancel_script_view,
+        ]
+        .iter()
+        {
+            if let Some(buffer) = view.get_buffer() {
+                connect!(relm, buffer, connect_changed(_), Msg::Changed);
+            }
+        }
+
+        Self { model, widgets }
+    }
+}
+
+/// Set all widgets to the values of the config
+fn show_config(widgets: &GtkWidgets, config: &config::Config) {
+    let profile = config.profile();
+    let profile_widgets = &widgets.profile;
+    profile_widgets.name_entry.set_text(&profile.name);
+    profile_widgets
+        .serial_number_entry
+        .set_text(profile.serial_number.as_deref().unwrap_or(""));
+    let flavour_index = config::Flavour::ALL
+        .iter()
+        .position(|flavour| *flavour == profile.flavour)
+        .unwrap_or(0);
+    profile_widgets
+        .flavour_combobox
+        .set_active_id(Some(&flavour_index.to_string()));
+    for (spin, size) in profile_widgets
+        .volume_spins
+        .iter()
+        .zip(profile.build_volume.iter())
+    {
+        spin.set_value(f64::from(*size));
+    }
+    for (spin, min) in profile_widgets
+        .axis_min_spins
+        .iter()
+        .zip(profile.axis_min.iter())
+    {
+        spin.set_value(f64::from(*min));
+    }
+    profile_widgets
+        .extruders_spin
+        .set_value(f64::from(profile.extruders));
+    profile_widgets
+        .heated_bed_btn
+        .set_active(profile.heated_bed);
+    profile_widgets
+        .heated_chamber_btn
+        .set_active(profile.heated_chamber);
+    profile_widgets
+        .max_hotend_spin
+        .set_value(f64::from(profile.max_hotend_temperature));
+    profile_widgets
+        .max_bed_spin
+        .set_value(f64::from(profile.max_bed_temperature));
+    profile_widgets
+        .max_chamber_spin
+        .set_value(f64::from(profile.max_chamber_temperature));
+    profile_widgets
+        .filament_diameter_spin
+        .set_value(f64::from(profile.filament_diameter));
+    profile_widgets
+        .filament_density_spin
+        .set_value(f64::from(profile.filament_density));
+    for (view, script) in [
+        (&profile_widgets.start_script_view, &profile.start_script),
+        (&profile_widgets.end_script_view, &profile.end_script),
+        (&profile_widgets.cancel_script_view, &profile.cancel_script),
+    ]
+    .iter()
+    {
+        if let Some(buffer) = view.get_buffer() {
+            buffer.set_text(script);
+        }
+    }
+    profile_widgets
+        .remove_btn
+        .set_sensitive(config.profiles.len() > 1);
+
+    let serial = &config.serial;
+    widgets
+        .data_bits_combobox
+        .set_active_id(Some(&serial.data_bits.to_string()));
+    widgets.parity_combobox.set_active_id(Some(&serial.parity));
+    widgets
+        .stop_bits_combobox
+        .set_active_id(Some(&serial.stop_bits.to_string()));
+    widgets
+        .flow_control_combobox
+        .set_active_id(Some(&serial.flow_control));
+    widgets.timeout_spin.set_value(serial.timeout as f64);
+    widgets
+        .handshake_timeout_spin
+        .set_value(serial.handshake_timeout as f64);
+
+    widgets
+        .temperature_interval_spin
+        .set_value(config.polling.temperature as f64);
+    widgets
+        .position_interval_spin
+        .set_value(config.polling.position as f64);
+
+    let jog = &config.jog;
+    widgets.xy_step_spin.set_value(f64::from(jog.xy_step));
+    widgets.z_step_spin.set_value(f64::from(jog.z_step));
+    widgets.e_step_spin.set_value(f64::from(jog.e_step));
+    widgets
+        .xy_feedrate_spin
+        .set_value(f64::from(jog.xy_feedrate));
+    widgets.z_feedrate_spin.set_value(f64::from(jog.z_feedrate));
+    widgets.e_feedrate_spin.set_value(f64::from(jog.e_feedrate));
+
+    widgets.maximized_btn.set_active(config.ui.maximized);
+    widgets.dark_theme_btn.set_active(config.ui.dark_theme);
+}
+
+/// The config with the values of the widgets
+fn read_config(widgets: &GtkWidgets, old: &config::Config) -> config::Config {
+    let active_id = |combobox: &gtk::ComboBoxText| {
+        combobox
+            .get_active_id()
+            .map(|id| id.to_string())
+            .unwrap_or_default()
+    };
+    let script = |view: &gtk::TextView| {
+        view.get_buffer()
+            .and_then(|buffer| {
+                let (start, end) = buffer.get_bounds();
+                buffer.get_text(&start, &end, false)
+            })
+            .map(|text| text.to_string())
+            .unwrap_or_default()
+    };
+
+    let mut config = old.clone();
+    let profile_widgets = &widgets.profile;
+
+    // Names have to be unique, otherwise the old name is kept
+    let name = profile_widgets.name_entry.get_text().trim().to_string();
+    let old_name = &old.profile().name;
+    let name = if name.is_empty()
+        || (name != *old_name && old.profiles.iter().any(|profile| profile.name == name))
+    {
+        old_name.clone()
+    } else {
+        name
+    };
+    let serial_number = profile_widgets
+        .serial_number_entry
+        .get_text()
+        .trim()
+        .to_string();
+
+    let profile = config.profile_mut();
+    profile.name = name.clone();
+    profile.serial_number = if serial_number.is_empty() {
+        None
+    } else {
+        Some(serial_number)
+    };
+    profile.flavour = active_id(&profile_widgets.flavour_combobox)
+        .parse::<usize>()
+        .ok()
+        .and_then(|index| config::Flavour::ALL.get(index).cloned())
+        .unwrap_or(config::Flavour::Auto);
+    for (size, spin) in profile
+        .build_volume
+        .iter_mut()
+        .zip(profile_widgets.volume_spins.iter())
+    {
+        *size = spin.get_value() as f32;
+    }
+    for (min, spin) in profile
+        .axis_min
+        .iter_mut()
+        .zip(profile_widgets.axis_min_spins.iter())
+    {
+        *min = spin.get_value() as f32;
+    }
+    profile.extruders = profile_widgets.extruders_spin.get_value_as_int() as u32;
+    profile.heated_bed = profile_widgets.heated_bed_btn.get_active();
+    profile.heated_chamber = profile_widgets.heated_chamber_btn.get_active();
+    profile.max_hotend_temperature = profile_widgets.max_hotend_spin.get_value() as f32;
+    profile.max_bed_temperature = profile_widgets.max_bed_spin.get_value() as f32;
+    profile.max_chamber_temperature = profile_widgets.max_chamber_spin.get_value() as f32;
+    profile.filament_diameter = profile_widgets.filament_diameter_spin.get_value() as f32;
+    profile.filament_density = profile_widgets.filament_density_spin.get_value() as f32;
+    profile.start_script = script(&profile_widgets.start_script_view);
+    profile.end_script = script(&profile_widgets.end_script_view);
+    profile.cancel_script = script(&profile_widgets.cancel_script_view);
+    config.active_profile = name;
+
+    config.serial = config::SerialConfig {
+        data_bits: active_id(&widgets.data_bits_combobox).parse().unwrap_or(8),
+        parity: active_id(&widgets.parity_combobox),
+        stop_bits: active_id(&widgets.stop_bits_combobox).parse().unwrap_or(1),
+        flow_control: active_id(&widgets.flow_control_combobox),
+        timeout: widgets.timeout_spin.get_value_as_int() as u64,
+        handshake_timeout: widgets.handshake_timeout_spin.get_value_as_int() as u64,
+    };
+    config.polling = config::PollingConfig {
+        temperature: widgets.temperature_interval_spin.get_value_as_int() as u64,
+        position: widgets.position_interval_spin.get_value_as_int() as u64,
+    };
+    config.jog = config::JogConfig {
+        xy_step: widgets.xy_step_spin.get_value() as f32,
+        z_step: widgets.z_step_spin.get_value() as f32,
+        e_step: widgets.e_step_spin.get_value() as f32,
+        xy_feedrate: widgets.xy_feedrate_spin.get_value() as f32,
+        z_feedrate: widgets.z_feedrate_spin.get_value() as f32,
+        e_feedrate: widgets.e_feedrate_spin.get_value() as f32,
+    };
+    config.ui = config::UiConfig {
+        maximized: widgets.maximized_btn.get_active(),
+        dark_theme: widgets.dark_theme_btn.get_active(),
+    };
+    config
+}
+
+/// A frame with a grid of labelled widgets
+fn create_frame(title: &str, rows: &[(&str, gtk::Widget)]) -> gtk::Frame {
+    let frame = gtk::Frame::new(Some(title));
+    let grid = gtk::Grid::new();
+    grid.set_column_spacing(10);
+    grid.set_row_spacing(3);
+    grid.set_border_width(5);
+
+    for (row, (label, widget)) in rows.iter().enumerate() {
+        let row = row as i32;
+        let label = gtk::Label::new(Some(label));
+        label.set_halign(gtk::Align::End);
+        label.set_valign(gtk::Align::Start);
+        widget.set_halign(gtk::Align::Start);
+        grid.attach(&label, 0, row, 1, 1);
+        grid.attach(widget, 1, row, 1, 1);
+    }
+
+    frame.add(&grid);
+    frame
+}
+
+fn show_capabilities(widgets: &FirmwareWidgets, capabilities: &firmware::Capabilities) {
+    let unknown = || "-".to_string();
+    let name = match (&capabilities.firmware_name, &capabilities.firmware_version) {
+        (Some(name), Some(version)) => format!("{} {}", name, version),
+        (Some(name), None) => name.clone(),
+        (None, _) => unknown(),
+    };
+    widgets.name_label.set_text(&name);
+    widgets
+        .machine_type_label
+        .set_text(&capabilities.machine_type.clone().unwrap_or_else(unknown));
+    widgets.extruders_label.set_text(
+        &capabilities
+            .extruder_count
+            .map(|count| count.to_string())
+            .unwrap_or_else(unknown),
+    );
+    widgets
+        .uuid_label
+        .set_text(&capabilities.uuid.clone().unwrap_or_else(unknown));
+    let lines: Vec<String> = capabilities
+        .capabilities
+        .iter()
+        .map(|(name, enabled)| format!("{}: {}", name, if *enabled { "yes" } else { "no" }))
+        .collect();
+    widgets.capabilities_label.set_text(&if lines.is_empty() {
+        unknown()
+    } else {
+        lines.join("\n")
+    });
+}
+
+/// A combobox with `(id, text)` entries
+fn create_combobox(entries: &[(&str, &str)]) -> gtk::ComboBoxText {
+    let combobox = gtk::ComboBoxText::new();
+    for (id, text) in entries.iter() {
+        combobox.append(Some(id), text);
+    }
+    combobox
+}
+
+/// A spin button showing as many digits as the step needs
+fn create_spin(min: f64, max: f64, step: f64) -> gtk::SpinButton {
+    let spin = gtk::SpinButton::with_range(min, max, step);
+    spin.set_digits(if step < 1.0 { 2 } else { 0 });
+    spin
+}
+
+/// A text view for a G-code script with one command per line
+fn create_script_view() -> gtk::TextView {
+    let view = gtk::TextView::new();
+    view.set_monospace(true);
+    view.set_size_request(300, 80);
+    view
+}
@@ -0,0 +1,320 @@
+use std::ops::Range;
+
+use crate::gcode;
+use crate::machine;
+
+/// Feature types the slicers mark with `;TYPE:` comments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Unknown,
+    OuterWall,
+    InnerWall,
+    Infill,
+    SolidInfill,
+    Bridge,
+    Support,
+    Skirt,
+    Custom,
+}
+
+impl Feature {
+    /// Feature of a comment like `TYPE:WALL-OUTER` (Cura) or `TYPE:External perimeter` (PrusaSlicer)
+    pub fn parse(comment: &str) -> Option<Feature> {
+        let name = comment.trim();
+        if name.len() < 5 || !name[..5].eq_ignore_ascii_case("TYPE:") {
+            return None;
+        }
+        Some(match name[5..].trim().to_ascii_lowercase().as_str() {
+            "wall-outer" | "external perimeter" | "outer wall" => Feature::OuterWall,
+            "wall-inner" | "perimeter" | "overhang perimeter" | "inner wall" | "overhang wall" => {
+                Feature::InnerWall
+            }
+            "fill" | "infill" | "internal infill" | "sparse infill" => Feature::Infill,
+            "skin"
+            | "solid infill"
+            | "internal solid infill"
+            | "top solid infill"
+            | "top surface"
+            | "bottom surface"
+            | "gap fill"
+            | "gap infill"
+            | "ironing" => Feature::SolidInfill,
+            "bridge" | "bridge infill" | "internal bridge" => Feature::Bridge,
+            "support"
+            | "support-interface"
+            | "support-infill"
+            | "support-roof"
+            | "support material"
+            | "support material interface"
+            | "support interface"
+            | "support transition" => Feature::Support,
+            "skirt" | "brim" | "skirt/brim" => Feature::Skirt,
+            _ => Feature::Custom,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveKind {
+    Travel,
+    Extrude,
+    /// Extruder only move pulling the filament back
+    Retract,
+    /// Extruder only move pushing the filament forward
+    Unretract,
+}
+
+/// A single move of the nozzle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    /// Index of the line in the print job
+    pub line: usize,
+    pub kind: MoveKind,
+    pub feature: Feature,
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+}
+
+/// A layer starts with the first extruding move at a new height
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub z: f32,
+    /// Index of the job line the layer starts with
+    pub first_line: usize,
+    pub segments: Range<usize>,
+}
+
+/// Extent of the printed part
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl BoundingBox {
+    /// A box around a single point
+    pub fn at(point: &[f32]) -> BoundingBox {
+        BoundingBox {
+            min: [point[0], point[1], point[2]],
+            max: [point[0], point[1], point[2]],
+        }
+    }
+
+    pub fn size(&self) -> [f32; 3] {
+        [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        ]
+    }
+
+    /// Grow the box to include the point
+    pub fn extend(&mut self, point: &[f32]) {
+        for (axis, value) in point.iter().take(3).enumerate() {
+            self.min[axis] = self.min[axis].min(*value);
+            self.max[axis] = self.max[axis].max(*value);
+        }
+    }
+}
+
+/// All moves of a G-code file, split into layers
+#[derive(Debug, Clone, Default)]
+pub struct Toolpath {
+    pub segments: Vec<Segment>,
+    pub layers: Vec<Layer>,
+    /// Extent of the extruding moves
+    pub bounding_box: Option<BoundingBox>,
+}
+
+impl Toolpath {
+    /// Follow the moves of a file. Empty lines are skipped when counting the lines,
+    /// just like the print job does, but their comments tell the feature types.
+    pub fn new(lines: &[gcode::Line]) -> Toolpath {
+        let mut machine = machine::MachineState::default();
+        let mut feature = Feature::Unknown;
+        let mut segments: Vec<Segment> = Vec::new();
+        // Height, first line and first segment of every layer
+        let mut layer_starts: Vec<(f32, usize, usize)> = Vec::new();
+        let mut bounding_box: Option<BoundingBox> = None;
+
+        // Index of the next line in the print job
+        let mut next_line = 0;
+
+        for line in lines.iter() {
+            if let Some(ref comment) = line.comment {
+                if let Some(new_feature) = Feature::parse(comment) {
+                    feature = new_feature;
+                }
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let index = next_line;
+            next_line += 1;
+
+            let before = machine.position;
+            machine.apply(line);
+            if !machine::is_move(line) {
+                continue;
+            }
+            let after = machine.position;
+
+            let moved = (0..3).any(|axis| (after[axis] - before[axis]).abs() > 0.0);
+            let extruded = after[3] - before[3];
+            let kind = match (moved, extruded) {
+                (true, extruded) if extruded > 0.0 => MoveKind::Extrude,
+                (true, _) => MoveKind::Travel,
+                (false, extruded) if extruded < 0.0 => MoveKind::Retract,
+                (false, extruded) if extruded > 0.0 => MoveKind::Unretract,
+                _ => continue,
+            };
+
+            if kind == MoveKind::Extrude
+                && layer_starts
+                    .last()
+                    .is_none_or(|(z, _, _)| (z - after[2]).abs() > 1e-4)
+            {
+                layer_starts.push((after[2], index, segments.len()));
+            }
+
+            // Arcs are drawn as short straight pieces
+            let mut from = before;
+            for to in machine::arc_points(line, &before, &after) {
+                if kind == MoveKind::Extrude {
+                    let bounding_box = bounding_box.get_or_insert_with(|| BoundingBox::at(&from));
+                    bounding_box.extend(&from);
+                    bounding_box.extend(&to);
+                }
+                segments.push(Segment {
+                    line: index,
+                    kind,
+                    feature,
+                    from: [from[0], from[1], from[2]],
+                    to: [to[0], to[1], to[2]],
+                });
+                from = to;
+            }
+        }
+
+        // The moves before the first layer are shown with it
+        let layers = layer_starts
+            .iter()
+            .enumerate()
+            .map(|(index, (z, first_line, start))| Layer {
+                z: *z,
+                first_line: *first_line,
+                segments: if index == 0 { 0 } else { *start }
+                    ..layer_starts
+                        .get(index + 1)
+                        .map_or(segments.len(), |(_, _, end)| *end),
+            })
+            .collect();
+
+        Toolpath {
+            segments,
+            layers,
+            bounding_box,
+        }
+    }
+
+    /// Index of the layer the move of a job line belongs to
+    pub fn layer_of_line(&self, line: usize) -> Option<usize> {
+        // The segments are in the order of their lines, only arcs have more than one
+        let segment = match self
+            .segments
+            .binary_search_by_key(&line, |segment| segment.line)
+        {
+            Ok(segment) | Err(segment) => segment,
+        };
+        self.layers
+            .iter()
+            .position(|layer| layer.segments.contains(&segment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_and_features() {
+        let lines: Vec<gcode::Line> = [
+            "; generated by a slicer",
+            "G28",
+            "G1 Z0.2 F3000",
+            ";TYPE:Skirt/Brim",
+            "G1 X10 Y10 E1",
+            "G1 E0.2",
+            "G1 Z0.4",
+            "G1 E1",
+            ";TYPE:External perimeter",
+            "G1 X20 E2",
+            "G1 X30 Z0.6",
+        ]
+        .iter()
+        .map(|line| gcode::Line::parse(line).unwrap())
+        .collect();
+        let toolpath = Toolpath::new(&lines);
+
+        let kinds: Vec<MoveKind> = toolpath.segments.iter().map(|s| s.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MoveKind::Travel,
+                MoveKind::Extrude,
+                MoveKind::Retract,
+                MoveKind::Travel,
+                MoveKind::Unretract,
+                MoveKind::Extrude,
+                MoveKind::Travel,
+            ]
+        );
+        // Comment only lines are not counted
+        assert_eq!(toolpath.segments[1].line, 2);
+        assert_eq!(toolpath.segments[1].feature, Feature::Skirt);
+        assert_eq!(toolpath.segments[5].feature, Feature::OuterWall);
+
+        assert_eq!(toolpath.layers.len(), 2);
+        assert_eq!(toolpath.layers[0].segments, 0..5);
+        assert_eq!(toolpath.layers[1].segments, 5..7);
+        assert_eq!(toolpath.layers[1].z, 0.4);
+        assert_eq!(toolpath.layers[0].first_line, 2);
+        assert_eq!(toolpath.layers[1].first_line, 6);
+        let bounding_box = toolpath.bounding_box.unwrap();
+        assert_eq!(bounding_box.min, [0.0, 0.0, 0.2]);
+        assert_eq!(bounding_box.max, [20.0, 10.0, 0.4]);
+
+        assert_eq!(toolpath.layer_of_line(0), Some(0));
+        assert_eq!(toolpath.layer_of_line(6), Some(1));
+        assert_eq!(toolpath.layer_of_line(8), None);
+    }
+
+    #[test]
+    fn arcs() {
+        let lines: Vec<gcode::Line> = [
+            "G1 Z0.2 F600",
+            "G1 X10 Y0 E1 F1200",
+            "G2 X10 Y20 I0 J10 E3",
+            "G1 X0 Y20 E4",
+        ]
+        .iter()
+        .map(|line| gcode::Line::parse(line).unwrap())
+        .collect();
+        let toolpath = Toolpath::new(&lines);
+
+        let arc: Vec<&Segment> = toolpath
+            .segments
+            .iter()
+            .filter(|segment| segment.line == 2)
+            .collect();
+        assert!(arc.len() > 10);
+        assert!(arc.iter().all(|segment| segment.kind == MoveKind::Extrude));
+        assert_eq!(arc.last().unwrap().to, [10.0, 20.0, 0.2]);
+        // The next move starts where the arc ended
+        assert_eq!(toolpath.segments.last().unwrap().from, [10.0, 20.0, 0.2]);
+        let bounding_box = toolpath.bounding_box.unwrap();
+        assert!(bounding_box.min[0] > -0.01 && bounding_box.min[0] < 0.01);
+        assert_eq!(bounding_box.max[1], 20.0);
+        assert_eq!(toolpath.layer_of_line(2), Some(0));
+    }
+}
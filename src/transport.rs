@@ -0,0 +1,111 @@
+use serialport::prelude::*;
+use std::io::{Read, Write};
+
+use crate::network;
+use crate::virtual_printer;
+
+/// A byte stream to the printer, e.g. a serial port
+///
+/// `read` returns an error of kind `TimedOut` if nothing arrived for a while, so the
+/// connection thread can look for commands in between.
+pub trait Transport: Send {
+    fn open(&mut self) -> Result<(), String>;
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize>;
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()>;
+    fn close(&mut self);
+    /// Shown in the log, e.g. `/dev/ttyACM0 at 115200 baud`
+    fn describe(&self) -> String;
+}
+
+pub fn not_open() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotConnected, "The port is not open")
+}
+
+/// The transport for a target of the connection bar, a serial port, a network bridge
+/// or the virtual printer
+pub fn for_target(target: &str, settings: SerialPortSettings) -> Box<dyn Transport> {
+    if target == virtual_printer::TARGET {
+        Box::new(virtual_printer::VirtualPrinter::new(settings.timeout))
+    } else if network::is_network(target) {
+        Box::new(network::TcpTransport::new(
+            target,
+            settings.timeout,
+            com_port(&settings),
+        ))
+    } else {
+        Box::new(SerialTransport::new(target, settings))
+    }
+}
+
+/// Only serial ports need a baud rate
+pub fn is_serial(target: &str) -> bool {
+    target != virtual_printer::TARGET && !network::is_network(target)
+}
+
+/// The settings in the coding of RFC 2217
+fn com_port(settings: &SerialPortSettings) -> network::ComPort {
+    network::ComPort {
+        baud_rate: settings.baud_rate,
+        data_size: match settings.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        },
+        parity: match settings.parity {
+            Parity::None => 1,
+            Parity::Odd => 2,
+            Parity::Even => 3,
+        },
+        stop_size: match settings.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        },
+        control: match settings.flow_control {
+            FlowControl::None => 1,
+            FlowControl::Software => 2,
+            FlowControl::Hardware => 3,
+        },
+    }
+}
+
+pub struct SerialTransport {
+    path: String,
+    settings: SerialPortSettings,
+    port: Option<Box<dyn SerialPort>>,
+}
+
+impl SerialTransport {
+    pub fn new(path: &str, settings: SerialPortSettings) -> Self {
+        SerialTransport {
+            path: path.to_string(),
+            settings,
+            port: None,
+        }
+    }
+}
+
+impl Transport for SerialTransport {
+    fn open(&mut self) -> Result<(), String> {
+        let port = serialport::open_with_settings(&self.path, &self.settings)
+            .map_err(|err| format!("{}: {}", self.path, err))?;
+        self.port = Some(port);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.port.as_mut().ok_or_else(not_open)?.read(buffer)
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.port.as_mut().ok_or_else(not_open)?.write_all(data)
+    }
+
+    fn close(&mut self) {
+        self.port = None;
+    }
+
+    fn describe(&self) -> String {
+        format!("{} at {} baud", self.path, self.settings.baud_rate)
+    }
+}
@@ -0,0 +1,694 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::gcode;
+use crate::transport::{not_open, Transport};
+
+/// Id of the "Virtual printer" entry in the port list
+pub const TARGET: &str = "virtual";
+
+const AMBIENT_TEMPERATURE: f64 = 21.0;
+/// Time constants of the heating curves in seconds
+const HOTEND_TIME_CONSTANT: f64 = 8.0;
+const BED_TIME_CONSTANT: f64 = 20.0;
+/// A heater is at its target if it is closer than this
+const TEMPERATURE_WINDOW: f64 = 1.0;
+/// Moves that are buffered before `ok` waits for the motion
+const PLANNER_SIZE: usize = 16;
+/// Feedrates in mm/min
+const DEFAULT_FEEDRATE: f64 = 3000.0;
+const HOMING_FEEDRATE: f64 = 3000.0;
+const STEPS_PER_MM: [f64; 3] = [80.0, 80.0, 400.0];
+/// Temperatures are reported this often while waiting for a heater
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// `busy:` is sent this often while a command blocks
+const BUSY_INTERVAL: Duration = Duration::from_secs(2);
+
+struct Heater {
+    temperature: f64,
+    target: f64,
+    time_constant: f64,
+}
+
+impl Heater {
+    fn new(time_constant: f64) -> Self {
+        Heater {
+            temperature: AMBIENT_TEMPERATURE,
+            target: 0.0,
+            time_constant,
+        }
+    }
+
+    /// Approach the target, or the room temperature if the heater is off
+    fn update(&mut self, seconds: f64) {
+        let goal = if self.target > 0.0 {
+            self.target
+        } else {
+            AMBIENT_TEMPERATURE
+        };
+        self.temperature +=
+            (goal - self.temperature) * (1.0 - (-seconds / self.time_constant).exp());
+    }
+
+    fn reached(&self) -> bool {
+        self.target <= 0.0 || (self.temperature - self.target).abs() < TEMPERATURE_WINDOW
+    }
+
+    fn power(&self) -> u32 {
+        if self.target > self.temperature {
+            127
+        } else {
+            0
+        }
+    }
+}
+
+/// What a blocking command waits for before its `ok`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wait {
+    Until(Instant),
+    Hotend,
+    Bed,
+}
+
+/// Faults in percent of the lines, set with `VIRTUAL_FAULT`
+#[derive(Debug, Clone, Copy, Default)]
+struct Faults {
+    drop_ok: f64,
+    corrupt: f64,
+    resend: f64,
+}
+
+/// An in-process printer that behaves like Marlin, to try the application without hardware
+///
+/// Faults are injected from the console with e.g. `VIRTUAL_FAULT DROP_OK=5 CORRUPT=2 RESEND=10`,
+/// the values are percents of the lines. `VIRTUAL_FAULT THERMAL=1` triggers a thermal runaway
+/// and `VIRTUAL_FAULT` alone turns all faults off.
+pub struct VirtualPrinter {
+    timeout: Duration,
+    open: bool,
+    last_update: Instant,
+    hotend: Heater,
+    bed: Heater,
+    /// X, Y, Z and E in mm
+    position: [f64; 4],
+    feedrate: f64,
+    relative: bool,
+    relative_e: bool,
+    /// End times of the buffered moves
+    moves: VecDeque<Instant>,
+    last_line: u32,
+    /// Bytes after the last newline
+    input: Vec<u8>,
+    /// Lines received while a command blocks
+    pending: VecDeque<String>,
+    waiting: Option<Wait>,
+    next_report: Instant,
+    next_busy: Instant,
+    auto_report: Option<Duration>,
+    next_auto_report: Instant,
+    output: VecDeque<u8>,
+    faults: Faults,
+    random: u64,
+    /// After a thermal runaway or `M112` nothing is answered anymore
+    halted: bool,
+}
+
+impl VirtualPrinter {
+    pub fn new(timeout: Duration) -> Self {
+        let now = Instant::now();
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+        VirtualPrinter {
+            timeout,
+            open: false,
+            last_update: now,
+            hotend: Heater::new(HOTEND_TIME_CONSTANT),
+            bed: Heater::new(BED_TIME_CONSTANT),
+            position: [0.0; 4],
+            feedrate: DEFAULT_FEEDRATE,
+            relative: false,
+            relative_e: false,
+            moves: VecDeque::new(),
+            last_line: 0,
+            input: Vec::new(),
+            pending: VecDeque::new(),
+            waiting: None,
+            next_report: now,
+            next_busy: now,
+            auto_report: None,
+            next_auto_report: now,
+            output: VecDeque::new(),
+            faults: Faults::default(),
+            // Xorshift must not start at zero
+            random: seed | 1,
+            halted: false,
+        }
+    }
+
+    /// Take the received bytes, complete lines are run as soon as the printer is ready
+    fn receive(&mut self, data: &[u8], now: Instant) {
+        for &byte in data {
+            if byte != b'\n' && byte != b'\r' {
+                self.input.push(byte);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.input).trim().to_string();
+            self.input.clear();
+            if line.is_empty() {
+                continue;
+            }
+            // The emergency parser sees M112 even while a command blocks
+            if gcode::Line::parse(&line).is_ok_and(|line| line.is('M', 112)) {
+                self.halt("Printer halted. kill() called!");
+            } else if !self.halted {
+                self.pending.push_back(line);
+            }
+        }
+        self.update(now);
+    }
+
+    /// Advance the simulation to `now` and run the pending lines
+    fn update(&mut self, now: Instant) {
+        let seconds = now
+            .saturating_duration_since(self.last_update)
+            .as_secs_f64();
+        self.last_update = now;
+        self.hotend.update(seconds);
+        self.bed.update(seconds);
+        while self.moves.front().is_some_and(|end| *end <= now) {
+            self.moves.pop_front();
+        }
+        if self.halted {
+            return;
+        }
+
+        if let Some(interval) = self.auto_report {
+            if now >= self.next_auto_report {
+                self.send(&self.temperature_report());
+                self.next_auto_report = now + interval;
+            }
+        }
+
+        loop {
+            if let Some(wait) = self.waiting {
+                let done = match wait {
+                    Wait::Until(end) => now >= end,
+                    Wait::Hotend => self.hotend.reached(),
+                    Wait::Bed => self.bed.reached(),
+                };
+                if !done {
+                    let heating = wait == Wait::Hotend || wait == Wait::Bed;
+                    if heating && now >= self.next_report {
+                        let report = format!("{} W:?", self.temperature_report());
+                        self.send(&report);
+                        self.next_report = now + REPORT_INTERVAL;
+                    }
+                    if now >= self.next_busy {
+                        self.send("echo:busy: processing");
+                        self.next_busy = now + BUSY_INTERVAL;
+                    }
+                    return;
+                }
+                self.waiting = None;
+                self.ok();
+            }
+            match self.pending.pop_front() {
+                Some(line) => self.run(&line, now),
+                None => return,
+            }
+        }
+    }
+
+    /// Check line number and checksum, then execute the line
+    fn run(&mut self, text: &str, now: Instant) {
+        let line = match gcode::Line::parse(text) {
+            Ok(line) => line,
+            Err(_) => {
+                self.send(&format!("echo:Unknown command: \"{}\"", text));
+                self.ok();
+                return;
+            }
+        };
+        if let Some(line_number) = line.line_number {
+            let checksum_valid = match (text.rfind('*'), line.checksum) {
+                (Some(pos), Some(checksum)) => gcode::checksum(&text[..pos]) == checksum,
+                _ => false,
+            };
+            if line.checksum.is_none() {
+                return self.request_resend("No Checksum with line number");
+            }
+            // The line got damaged on its way
+            if !checksum_valid || self.chance(self.faults.resend) {
+                return self.request_resend("checksum mismatch");
+            }
+            if line.is('M', 110) {
+                self.last_line = line.get('N').map_or(line_number, |number| number as u32);
+            } else if line_number != self.last_line + 1 {
+                return self.request_resend("Line Number is not Last Line Number+1");
+            } else {
+                self.last_line = line_number;
+            }
+        } else if line.is('M', 110) {
+            self.last_line = line.get('N').unwrap_or(0.0) as u32;
+        }
+        self.execute(&line, now);
+    }
+
+    fn request_resend(&mut self, error: &str) {
+        self.send(&format!("Error:{}, Last Line: {}", error, self.last_line));
+        self.send(&format!("Resend: {}", self.last_line + 1));
+        self.ok();
+    }
+
+    fn execute(&mut self, line: &gcode::Line, now: Instant) {
+        if let Some(ref text) = line.text {
+            if line.command.is_none() {
+                return self.fault(text);
+            }
+        }
+        let command = match line.command {
+            Some(command) => command,
+            None => return self.ok(),
+        };
+        match (command.letter, command.number) {
+            ('G', 0) | ('G', 1) => {
+                if let Some(feedrate) = line.get('F') {
+                    self.feedrate = feedrate;
+                }
+                let start = self.position;
+                for (axis, &letter) in ['X', 'Y', 'Z', 'E'].iter().enumerate() {
+                    if let Some(value) = line.get(letter) {
+                        let relative = self.relative || (axis == 3 && self.relative_e);
+                        self.position[axis] = if relative {
+                            self.position[axis] + value
+                        } else {
+                            value
+                        };
+                    }
+                }
+                let distance = self.distance(start);
+                self.plan_move(distance, self.feedrate, now);
+                // The planner is full, the ok comes when a move is done
+                if self.moves.len() > PLANNER_SIZE {
+                    let free = self.moves[self.moves.len() - PLANNER_SIZE - 1];
+                    self.wait(Wait::Until(free), now);
+                } else {
+                    self.ok();
+                }
+            }
+            ('G', 4) => {
+                let dwell = line.get('P').unwrap_or(0.0) / 1000.0 + line.get('S').unwrap_or(0.0);
+                let end = self.motion_end(now) + Duration::from_secs_f64(dwell.max(0.0));
+                self.wait(Wait::Until(end), now);
+            }
+            ('G', 28) => {
+                let start = self.position;
+                let all = !['X', 'Y', 'Z'].iter().any(|&letter| line.has(letter));
+                for (axis, &letter) in ['X', 'Y', 'Z'].iter().enumerate() {
+                    if all || line.has(letter) {
+                        self.position[axis] = 0.0;
+                    }
+                }
+                let distance = self.distance(start);
+                self.plan_move(distance, HOMING_FEEDRATE, now);
+                self.wait(Wait::Until(self.motion_end(now)), now);
+            }
+            ('G', 90) => {
+                self.relative = false;
+                self.relative_e = false;
+                self.ok();
+            }
+            ('G', 91) => {
+                self.relative = true;
+                self.ok();
+            }
+            ('G', 92) => {
+                for (axis, &letter) in ['X', 'Y', 'Z', 'E'].iter().enumerate() {
+                    if let Some(value) = line.get(letter) {
+                        self.position[axis] = value;
+                    }
+                }
+                self.ok();
+            }
+            ('M', 82) => {
+                self.relative_e = false;
+                self.ok();
+            }
+            ('M', 83) => {
+                self.relative_e = true;
+                self.ok();
+            }
+            ('M', 104) | ('M', 109) => {
+                if let Some(target) = line.get('S').or_else(|| line.get('R')) {
+                    self.hotend.target = target;
+                }
+                if command.number == 109 {
+                    self.wait(Wait::Hotend, now);
+                } else {
+                    self.ok();
+                }
+            }
+            ('M', 140) | ('M', 190) => {
+                if let Some(target) = line.get('S').or_else(|| line.get('R')) {
+                    self.bed.target = target;
+                }
+                if command.number == 190 {
+                    self.wait(Wait::Bed, now);
+                } else {
+                    self.ok();
+                }
+            }
+            ('M', 105) => {
+                let report = format!("ok {}", self.temperature_report());
+                self.send(&report);
+            }
+            ('M', 114) => {
+                let report = self.position_report();
+                self.send(&report);
+                self.ok();
+            }
+            ('M', 115) => {
+                self.send(
+                    "FIRMWARE_NAME:Marlin 2.0.9.3 (Virtual printer) \
+                     SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0 \
+                     MACHINE_TYPE:Virtual printer EXTRUDER_COUNT:1 \
+                     UUID:cede2a2f-41a2-4748-9b12-c55c62f367ff",
+                );
+                for capability in [
+                    "SERIAL_XON_XOFF:0",
+                    "BINARY_FILE_TRANSFER:0",
+                    "EEPROM:0",
+                    "AUTOREPORT_TEMP:1",
+                    "AUTOREPORT_POS:0",
+                    "PROGRESS:0",
+                    "PRINT_JOB:1",
+                    "SDCARD:0",
+                    "AUTOLEVEL:0",
+                    "EMERGENCY_PARSER:1",
+                    "HOST_ACTION_COMMANDS:0",
+                    "PROMPT_SUPPORT:0",
+                    "EXTENDED_M20:0",
+                ]
+                .iter()
+                {
+                    self.send(&format!("Cap:{}", capability));
+                }
+                self.ok();
+            }
+            ('M', 155) => {
+                let interval = line.get('S').unwrap_or(0.0);
+                self.auto_report = if interval > 0.0 {
+                    Some(Duration::from_secs_f64(interval))
+                } else {
+                    None
+                };
+                self.next_auto_report = now;
+                self.ok();
+            }
+            ('M', 400) => self.wait(Wait::Until(self.motion_end(now)), now),
+            ('M', 503) => {
+                self.send("echo:; Steps per unit:");
+                self.send(&format!(
+                    "echo:  M92 X{} Y{} Z{} E93",
+                    STEPS_PER_MM[0], STEPS_PER_MM[1], STEPS_PER_MM[2]
+                ));
+                self.ok();
+            }
+            _ => self.ok(),
+        }
+    }
+
+    /// `VIRTUAL_FAULT DROP_OK=5 CORRUPT=2 RESEND=10 THERMAL=1`
+    fn fault(&mut self, text: &str) {
+        let mut words = text.split_whitespace();
+        if words.next() != Some("VIRTUAL_FAULT") {
+            self.send(&format!("echo:Unknown command: \"{}\"", text));
+            return self.ok();
+        }
+        let mut faults = Faults::default();
+        let mut thermal = false;
+        for word in words {
+            let mut parts = word.splitn(2, '=');
+            let name = parts.next().unwrap_or("");
+            let value: f64 = parts
+                .next()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0.0);
+            match name {
+                "DROP_OK" => faults.drop_ok = value,
+                "CORRUPT" => faults.corrupt = value,
+                "RESEND" => faults.resend = value,
+                "THERMAL" => thermal = value > 0.0,
+                _ => self.send(&format!("echo:Unknown fault {}", name)),
+            }
+        }
+        self.faults = faults;
+        if thermal {
+            self.send("Error:Thermal Runaway, system stopped! Heater_ID: 0");
+            self.halt("Printer halted. kill() called!");
+        } else {
+            self.ok();
+        }
+    }
+
+    /// Block the following lines, the `ok` is sent when the wait is over
+    fn wait(&mut self, wait: Wait, now: Instant) {
+        self.waiting = Some(wait);
+        self.next_report = now + REPORT_INTERVAL;
+        self.next_busy = now + BUSY_INTERVAL;
+    }
+
+    fn halt(&mut self, error: &str) {
+        if !self.halted {
+            self.send(&format!("Error:{}", error));
+        }
+        self.halted = true;
+        self.hotend.target = 0.0;
+        self.bed.target = 0.0;
+        self.pending.clear();
+        self.waiting = None;
+    }
+
+    fn distance(&self, start: [f64; 4]) -> f64 {
+        let squared: f64 = (0..3)
+            .map(|axis| (self.position[axis] - start[axis]).powi(2))
+            .sum();
+        if squared > 0.0 {
+            squared.sqrt()
+        } else {
+            (self.position[3] - start[3]).abs()
+        }
+    }
+
+    /// Queue a move behind the buffered ones
+    fn plan_move(&mut self, distance: f64, feedrate: f64, now: Instant) {
+        let seconds = distance / (feedrate.max(1.0) / 60.0);
+        let end = self.motion_end(now) + Duration::from_secs_f64(seconds);
+        self.moves.push_back(end);
+    }
+
+    fn motion_end(&self, now: Instant) -> Instant {
+        self.moves.back().copied().unwrap_or(now).max(now)
+    }
+
+    fn temperature_report(&self) -> String {
+        format!(
+            "T:{:.2} /{:.2} B:{:.2} /{:.2} @:{} B@:{}",
+            self.hotend.temperature,
+            self.hotend.target,
+            self.bed.temperature,
+            self.bed.target,
+            self.hotend.power(),
+            self.bed.power()
+        )
+    }
+
+    fn position_report(&self) -> String {
+        format!(
+            "X:{:.2} Y:{:.2} Z:{:.2} E:{:.2} Count X:{} Y:{} Z:{}",
+            self.position[0],
+            self.position[1],
+            self.position[2],
+            self.position[3],
+            (self.position[0] * STEPS_PER_MM[0]).round(),
+            (self.position[1] * STEPS_PER_MM[1]).round(),
+            (self.position[2] * STEPS_PER_MM[2]).round()
+        )
+    }
+
+    fn ok(&mut self) {
+        if !self.chance(self.faults.drop_ok) {
+            self.send("ok");
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        let mut bytes = line.as_bytes().to_vec();
+        // Noise on the line replaces a character
+        if !bytes.is_empty() && self.chance(self.faults.corrupt) {
+            let pos = self.next_random() as usize % bytes.len();
+            bytes[pos] = b'!' + (self.next_random() % 94) as u8;
+        }
+        self.output.extend(bytes);
+        self.output.push_back(b'\n');
+    }
+
+    fn chance(&mut self, percent: f64) -> bool {
+        percent > 0.0 && ((self.next_random() % 10_000) as f64) < percent * 100.0
+    }
+
+    /// Xorshift, good enough for faults
+    fn next_random(&mut self) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        self.random
+    }
+}
+
+impl Transport for VirtualPrinter {
+    fn open(&mut self) -> Result<(), String> {
+        *self = VirtualPrinter::new(self.timeout);
+        self.open = true;
+        self.send("start");
+        self.send("echo:Marlin 2.0.9.3 (Virtual printer)");
+        self.send("echo: Last Updated: 2021-12-25 | Author: (gcode1000)");
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        if !self.open {
+            return Err(not_open());
+        }
+        self.update(Instant::now());
+        // Behave like a serial port that waits for data
+        if self.output.is_empty() {
+            std::thread::sleep(self.timeout);
+            self.update(Instant::now());
+        }
+        if self.output.is_empty() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        let n = buffer.len().min(self.output.len());
+        for (byte, received) in buffer.iter_mut().zip(self.output.drain(..n)) {
+            *byte = received;
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if !self.open {
+            return Err(not_open());
+        }
+        self.receive(data, Instant::now());
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.open = false;
+    }
+
+    fn describe(&self) -> String {
+        "Virtual printer".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(printer: &mut VirtualPrinter) -> Vec<String> {
+        let text: Vec<u8> = printer.output.drain(..).collect();
+        String::from_utf8(text)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reports() {
+        let mut printer = VirtualPrinter::new(Duration::from_millis(1));
+        let now = Instant::now();
+        printer.receive(b"M105\nG1 X10 Y20 F600\nM114\nM115\n", now);
+        let lines = output(&mut printer);
+        assert_eq!(lines[0], "ok T:21.00 /0.00 B:21.00 /0.00 @:0 B@:0");
+        assert_eq!(lines[1], "ok");
+        assert!(lines[2].starts_with("X:10.00 Y:20.00 Z:0.00 E:0.00 Count X:800 Y:1600"));
+        assert!(lines[4].starts_with("FIRMWARE_NAME:Marlin"));
+        assert!(lines.contains(&"Cap:AUTOREPORT_TEMP:1".to_string()));
+        assert_eq!(lines.last().unwrap(), "ok");
+
+        // M400 waits for the move of 2.2 s
+        printer.receive(b"M400\n", now);
+        printer.update(now + Duration::from_secs(1));
+        assert!(!output(&mut printer).contains(&"ok".to_string()));
+        printer.update(now + Duration::from_secs(3));
+        assert_eq!(output(&mut printer), vec!["ok"]);
+    }
+
+    #[test]
+    fn heating() {
+        let mut printer = VirtualPrinter::new(Duration::from_millis(1));
+        let start = Instant::now();
+        printer.receive(b"M109 S200\nM105\n", start);
+        let mut lines = output(&mut printer);
+        for second in 1..120 {
+            printer.update(start + Duration::from_secs(second));
+            lines.extend(output(&mut printer));
+        }
+        let ok = lines.iter().position(|line| line == "ok").unwrap();
+        assert!(lines[..ok].iter().any(|line| line.ends_with("W:?")));
+        assert!(lines[..ok].contains(&"echo:busy: processing".to_string()));
+        // M105 only runs after M109 is done
+        assert!(lines[ok + 1].starts_with("ok T:199.") || lines[ok + 1].starts_with("ok T:200."));
+    }
+
+    #[test]
+    fn line_numbers() {
+        let mut printer = VirtualPrinter::new(Duration::from_millis(1));
+        let now = Instant::now();
+        let line = |number: u32, code: &str| gcode::Line::parse(code).unwrap().framed(number);
+        let text = format!(
+            "{}\n{}\n{}\nN2 G28*7\n",
+            line(0, "M110 N0"),
+            line(1, "G90"),
+            line(3, "M105")
+        );
+        printer.receive(text.as_bytes(), now);
+        assert_eq!(
+            output(&mut printer),
+            vec![
+                "ok",
+                "ok",
+                "Error:Line Number is not Last Line Number+1, Last Line: 1",
+                "Resend: 2",
+                "ok",
+                "Error:checksum mismatch, Last Line: 1",
+                "Resend: 2",
+                "ok"
+            ]
+        );
+
+        // Extended commands are understood behind a line number too
+        let text = format!(
+            "{}\n{}\n",
+            line(2, "VIRTUAL_FAULT DROP_OK=100"),
+            line(3, "G90")
+        );
+        printer.receive(text.as_bytes(), now);
+        assert_eq!(output(&mut printer), Vec::<String>::new());
+
+        printer.receive(b"VIRTUAL_FAULT THERMAL=1\nM105\n", now);
+        assert_eq!(
+            output(&mut printer),
+            vec![
+                "Error:Thermal Runaway, system stopped! Heater_ID: 0",
+                "Error:Printer halted. kill() called!"
+            ]
+        );
+    }
+}